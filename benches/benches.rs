@@ -5,7 +5,7 @@ extern crate test;
 
 mod bitset {
     use test;
-    use hibitset::BitSet;
+    use hibitset::{BitSet, Growth};
 
     #[bench]
     fn add(b: &mut test::Bencher) {
@@ -14,6 +14,27 @@ mod bitset {
         b.iter(|| range.next().map(|i| bitset.add(i)))
     }
 
+    fn add_incremental(growth: Growth, b: &mut test::Bencher) {
+        b.iter(|| {
+            let mut bitset = BitSet::new();
+            bitset.set_growth(growth);
+            for i in 0..100_000 {
+                bitset.add(i * 8);
+            }
+            bitset
+        })
+    }
+
+    #[bench]
+    fn add_incremental_geometric(b: &mut test::Bencher) {
+        add_incremental(Growth::Geometric, b);
+    }
+
+    #[bench]
+    fn add_incremental_exact(b: &mut test::Bencher) {
+        add_incremental(Growth::Exact, b);
+    }
+
     #[bench]
     fn remove_set(b: &mut test::Bencher) {
         let mut bitset = BitSet::with_capacity(1_000_000);
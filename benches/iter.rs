@@ -8,7 +8,7 @@ extern crate rayon;
 #[cfg(feature = "parallel")]
 use rayon::iter::ParallelIterator;
 
-use hibitset::{BitSet, BitSetLike};
+use hibitset::{BitSet, BitSetAnd, BitSetAnd3, BitSetLike};
 
 use test::{Bencher, black_box};
 
@@ -61,6 +61,28 @@ fn iter_1000000(b: &mut Bencher) {
     bench(1000000, Seq, b);
 }
 
+fn three_sets() -> [BitSet; 3] {
+    let mut rng = XorShiftRng::new_unseeded();
+    let mut sets = [BitSet::new(), BitSet::new(), BitSet::new()];
+    for set in &mut sets {
+        for _ in 0..100_000 {
+            set.add(rng.gen_range(0, 1048576));
+        }
+    }
+    sets
+}
+
+#[bench]
+fn iter_and_nested(b: &mut Bencher) {
+    let [x, y, z] = three_sets();
+    b.iter(|| black_box(BitSetAnd(BitSetAnd(&x, &y), &z).iter().map(black_box).count()));
+}
+
+#[bench]
+fn iter_and3(b: &mut Bencher) {
+    let [x, y, z] = three_sets();
+    b.iter(|| black_box(BitSetAnd3(&x, &y, &z).iter().map(black_box).count()));
+}
 
 #[cfg(feature = "parallel")]
 mod par {
@@ -1,12 +1,13 @@
 use std::default::Default;
 use std::fmt::{Debug, Error as FormatError, Formatter};
 use std::iter::repeat;
+use std::ops::Range;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 use atom::AtomSetOnce;
 
 use util::*;
-use {BitSetLike, DrainableBitSet};
+use {BitSet, BitSetLike, DrainableBitSet};
 
 /// This is similar to a [`BitSet`] but allows setting of value
 /// without unique ownership of the structure
@@ -39,9 +40,31 @@ impl AtomicBitSet {
         Default::default()
     }
 
+    /// Creates an empty `AtomicBitSet`, with the storage for up to `max`
+    /// indices allocated up front.
+    ///
+    /// The words of layer 0 are otherwise allocated lazily by the first
+    /// add into each block, so preallocating them guarantees that
+    /// concurrent adds within the capacity never allocate.
+    pub fn with_capacity(max: Index) -> AtomicBitSet {
+        if (MAX_EID as u32) < max {
+            panic!("Expected index to be less then {}, found {}", MAX_EID, max);
+        }
+        let value = AtomicBitSet::new();
+        let last = ::std::cmp::min(max.offset(SHIFT2), value.layer1.len() - 1);
+        for block in &value.layer1[..last + 1] {
+            block.allocate();
+        }
+        value
+    }
+
     /// Adds `id` to the `AtomicBitSet`. Returns `true` if the value was
     /// already in the set.
     ///
+    /// The result comes from a single atomic `fetch_or` of the word in
+    /// layer 0, so when several threads race to add the same index exactly
+    /// one of them observes `false`.
+    ///
     /// Because we cannot safely extend an AtomicBitSet without unique ownership
     /// this will panic if the Index is out of range.
     #[inline]
@@ -59,6 +82,60 @@ impl AtomicBitSet {
         set
     }
 
+    /// Atomically adds every index in `range` to the set.
+    ///
+    /// Whole words of layer 0 are set with a single `fetch_or` each, and
+    /// the upper layers are updated once per word they summarize, instead
+    /// of once per index. Other threads may add to the set at the same time,
+    /// even with overlapping ranges, since OR-ing in bits is commutative.
+    /// Like [`add_atomic`], this panics if the range is out of bounds.
+    ///
+    /// [`add_atomic`]: #method.add_atomic
+    pub fn add_range(&self, range: Range<Index>) {
+        if range.start >= range.end {
+            return;
+        }
+        let (first, last) = (range.start, range.end - 1);
+        if (MAX_EID as u32) <= last {
+            panic!("Expected index to be less then {}, found {}", MAX_EID, last);
+        }
+        // The bits `lo..=hi` of a word.
+        let bits = |lo: usize, hi: usize| (!0 << lo) & (!0 >> ((1 << BITS) - 1 - hi));
+
+        let (w0, w1) = (first.offset(SHIFT1), last.offset(SHIFT1));
+        let (mut p2, mut m2, mut m3) = (w0 >> (2 * BITS), 0, 0);
+        for p1 in (w0 >> BITS)..((w1 >> BITS) + 1) {
+            let lo = ::std::cmp::max(w0, p1 << BITS) & ((1 << BITS) - 1);
+            let hi = ::std::cmp::min(w1, (p1 << BITS) | ((1 << BITS) - 1)) & ((1 << BITS) - 1);
+
+            let block = &self.layer1[p1];
+            block.allocate();
+            let l0 = block.atom.get().unwrap();
+            for (row, word) in (lo..).zip(&l0[lo..(hi + 1)]) {
+                let w = (p1 << BITS) | row;
+                let mut mask = !0;
+                if w == w0 {
+                    mask &= !0 << first.row(SHIFT0);
+                }
+                if w == w1 {
+                    mask &= bits(0, last.row(SHIFT0));
+                }
+                word.fetch_or(mask, Ordering::Relaxed);
+            }
+            block.mask.fetch_or(bits(lo, hi), Ordering::Relaxed);
+
+            if p1 >> BITS != p2 {
+                self.layer2[p2].fetch_or(m2, Ordering::Relaxed);
+                p2 = p1 >> BITS;
+                m2 = 0;
+            }
+            m2 |= 1 << (p1 & ((1 << BITS) - 1));
+            m3 |= 1 << p2;
+        }
+        self.layer2[p2].fetch_or(m2, Ordering::Relaxed);
+        self.layer3.fetch_or(m3, Ordering::Relaxed);
+    }
+
     /// Adds `id` to the `BitSet`. Returns `true` if the value was
     /// already in the set.
     #[inline]
@@ -116,6 +193,45 @@ impl AtomicBitSet {
         self.layer1[i].contains(id)
     }
 
+    /// Copies the members of the set into a new `BitSet`, taking care to
+    /// not tear the words of layer 0 while they are being added to.
+    ///
+    /// Every allocated block of layer 0 is read twice, and read again
+    /// while the two reads disagree, up to a few times. The upper layers
+    /// are ignored, since they are only set after the words below them,
+    /// and are rebuilt from layer 0 instead. Every member of the snapshot
+    /// has been added to the set, and members which were added before the
+    /// call are always included.
+    ///
+    /// This is best-effort and not linearizable: under heavy contention
+    /// the blocks are read at different times, so the snapshot may include
+    /// some concurrent adds but not others that happened before them.
+    pub fn consistent_snapshot(&self) -> BitSet {
+        const RETRIES: usize = 8;
+
+        let mut words = Vec::new();
+        let mut block = [0; 1 << BITS];
+        for (p1, l1) in self.layer1.iter().enumerate() {
+            let l0 = match l1.atom.get() {
+                Some(l0) => l0,
+                None => continue,
+            };
+            for _ in 0..RETRIES {
+                for (word, atomic) in block.iter_mut().zip(l0.iter()) {
+                    *word = atomic.load(Ordering::Relaxed);
+                }
+                if block.iter().zip(l0.iter()).all(|(&word, atomic)| atomic.load(Ordering::Relaxed) == word) {
+                    break;
+                }
+            }
+            if block.iter().any(|&word| word != 0) {
+                words.resize((p1 + 1) << BITS, 0);
+                words[p1 << BITS..].copy_from_slice(&block);
+            }
+        }
+        BitSet::from_layer0(words)
+    }
+
     /// Clear all bits in the set
     pub fn clear(&mut self) {
         // This is the same hierarchical-striding used in the iterators.
@@ -176,6 +292,20 @@ impl BitSetLike for AtomicBitSet {
     fn contains(&self, i: Index) -> bool {
         self.contains(i)
     }
+    /// Adds up the set bits of the words in every non-empty block, loading
+    /// each word with `Ordering::Relaxed`.
+    ///
+    /// If the set is added to concurrently, the result is an approximate
+    /// snapshot which may or may not include the concurrent adds.
+    fn count(&self) -> usize {
+        self.layer1
+            .iter()
+            .filter(|block| block.mask.load(Ordering::Relaxed) != 0)
+            .filter_map(|block| block.atom.get())
+            .flat_map(|l0| l0.iter())
+            .map(|word| word.load(Ordering::Relaxed).count_ones() as usize)
+            .sum()
+    }
 }
 
 impl DrainableBitSet for AtomicBitSet {
@@ -214,11 +344,15 @@ impl AtomicBlock {
         }
     }
 
-    fn add(&self, id: Index) -> bool {
+    fn allocate(&self) {
         if self.atom.is_none() {
             let v = Box::new(unsafe { ::std::mem::zeroed() });
             self.atom.set_if_none(v);
         }
+    }
+
+    fn add(&self, id: Index) -> bool {
+        self.allocate();
 
         let (i, m) = (id.row(SHIFT1), id.mask(SHIFT0));
         let old = self.atom.get().unwrap()[i].fetch_or(m, Ordering::Relaxed);
@@ -297,6 +431,140 @@ mod atomic_set_test {
         }
     }
 
+    #[test]
+    fn add_atomic_race() {
+        use std::sync::{Arc, Barrier};
+        use std::thread;
+
+        const THREADS: usize = 8;
+
+        for round in 0..20 {
+            let set = Arc::new(AtomicBitSet::new());
+            let barrier = Arc::new(Barrier::new(THREADS));
+            let threads: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    let (set, barrier) = (set.clone(), barrier.clone());
+                    thread::spawn(move || {
+                        barrier.wait();
+                        set.add_atomic(round * 1_000 + 7)
+                    })
+                })
+                .collect();
+            let results: Vec<bool> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+            assert_eq!(results.iter().filter(|&&already| !already).count(), 1);
+            assert!(set.contains(round * 1_000 + 7));
+        }
+    }
+
+    #[test]
+    fn add_range() {
+        let edges = [0, 1, 63, 64, 65, 4_095, 4_096, 4_097, 262_143, 262_144, 300_000];
+        for &start in &edges {
+            for &end in &edges {
+                let set = AtomicBitSet::new();
+                set.add_range(start..end);
+                assert!((&set).iter().eq(start..end), "{}..{}", start, end);
+            }
+        }
+
+        let set = AtomicBitSet::new();
+        set.add_range(16_777_000..16_777_216);
+        assert!((&set).iter().eq(16_777_000..16_777_216));
+    }
+
+    #[test]
+    fn add_range_concurrent() {
+        use std::sync::{Arc, Barrier};
+        use std::thread;
+
+        const THREADS: u32 = 8;
+        const LEN: u32 = 70_001;
+
+        let set = Arc::new(AtomicBitSet::new());
+        let barrier = Arc::new(Barrier::new(THREADS as usize));
+        let threads: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let (set, barrier) = (set.clone(), barrier.clone());
+                thread::spawn(move || {
+                    barrier.wait();
+                    set.add_range(1_000 + t * LEN..1_000 + (t + 1) * LEN);
+                    // overlaps the neighbouring ranges
+                    set.add_range(1_000 + t * LEN - 500..1_000 + t * LEN + 500);
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert!((&*set).iter().eq(500..1_000 + THREADS * LEN));
+        assert_eq!(set.count(), (THREADS * LEN + 500) as usize);
+    }
+
+    #[test]
+    fn consistent_snapshot() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::thread;
+        use BitSet;
+
+        let set = Arc::new(AtomicBitSet::new());
+        let done = Arc::new(AtomicBool::new(false));
+        let member = |i: u32| i % 3 == 1 || i % 4_099 == 7;
+
+        let writer = {
+            let (set, done) = (set.clone(), done.clone());
+            thread::spawn(move || {
+                for i in (0..2_000_000).filter(|&i| member(i)) {
+                    set.add_atomic(i);
+                }
+                done.store(true, Ordering::SeqCst);
+            })
+        };
+
+        let mut previous = BitSet::new();
+        while !done.load(Ordering::SeqCst) {
+            let snapshot = set.consistent_snapshot();
+            // no phantom members, and nothing seen before goes missing
+            assert!((&snapshot).iter().all(|i| member(i) && i < 2_000_000));
+            assert!(previous.difference_is_empty(&snapshot));
+            assert_eq!(snapshot.cached_len(), (&snapshot).iter().count());
+            previous = snapshot;
+        }
+        writer.join().unwrap();
+
+        let snapshot = set.consistent_snapshot();
+        assert!((&snapshot).iter().eq((0..2_000_000).filter(|&i| member(i))));
+        assert!(AtomicBitSet::new().consistent_snapshot().is_empty());
+    }
+
+    #[test]
+    fn count() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let c = Arc::new(AtomicBitSet::new());
+        let workers = (0..4)
+            .map(|n| {
+                let c = c.clone();
+                thread::spawn(move || {
+                    for i in 0..50_000 {
+                        c.add_atomic(i * 7 + n * 3);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let distinct = (0..4u32)
+            .flat_map(|n| (0..50_000u32).map(move |i| i * 7 + n * 3))
+            .collect::<::std::collections::HashSet<_>>();
+        assert_eq!(c.count(), distinct.len());
+        assert_eq!(c.count(), (&*c).iter().count());
+        assert_eq!(AtomicBitSet::new().count(), 0);
+    }
+
     #[test]
     fn add_atomic() {
         let c = AtomicBitSet::new();
@@ -323,6 +591,38 @@ mod atomic_set_test {
         }
     }
 
+    #[test]
+    fn with_capacity() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let c = Arc::new(AtomicBitSet::with_capacity(100_000));
+        let blocks = (100_000 >> ::util::SHIFT2) + 1;
+        assert!(c.layer1[..blocks].iter().all(|block| !block.atom.is_none()));
+        assert!(c.layer1[blocks..].iter().all(|block| block.atom.is_none()));
+
+        let workers = (0..4)
+            .map(|n| {
+                let c = c.clone();
+                thread::spawn(move || {
+                    for i in (n..100_000).step_by(4) {
+                        assert!(!c.add_atomic(i));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert!(c.layer1[blocks..].iter().all(|block| block.atom.is_none()));
+        assert_eq!((&*c).iter().count(), 100_000);
+        assert!((0..100_000).all(|i| c.contains(i)));
+
+        let full = AtomicBitSet::with_capacity(::util::MAX_EID as u32);
+        assert!(full.layer1.iter().all(|block| !block.atom.is_none()));
+    }
+
     #[test]
     fn remove() {
         let mut c = AtomicBitSet::new();
@@ -0,0 +1,91 @@
+//! A text encoding of `BitSet`s, made of the compact byte encoding in
+//! URL-safe base64 without padding.
+
+use std::error::Error;
+use std::fmt;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+
+use BitSet;
+
+/// The error returned by [`BitSet::from_base64`].
+///
+/// [`BitSet::from_base64`]: struct.BitSet.html#method.from_base64
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The string isn't valid URL-safe base64.
+    InvalidBase64,
+    /// The decoded bytes aren't a valid compact encoding of a set.
+    InvalidEncoding,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::InvalidBase64 => write!(f, "invalid base64"),
+            ParseError::InvalidEncoding => write!(f, "invalid compact encoding of a bit set"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl BitSet {
+    /// Encodes the set into a string, which can be turned back into a set
+    /// with [`from_base64`].
+    ///
+    /// The string is the [`encode_compact`] bytes in URL-safe base64
+    /// without padding, so it can be pasted into configuration files and
+    /// URLs as is.
+    ///
+    /// [`from_base64`]: #method.from_base64
+    /// [`encode_compact`]: #method.encode_compact
+    pub fn to_base64(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.encode_compact())
+    }
+
+    /// Decodes a set encoded with [`to_base64`].
+    ///
+    /// [`to_base64`]: #method.to_base64
+    pub fn from_base64(s: &str) -> Result<BitSet, ParseError> {
+        let bytes = URL_SAFE_NO_PAD.decode(s).map_err(|_| ParseError::InvalidBase64)?;
+        BitSet::decode_compact(&bytes).ok_or(ParseError::InvalidEncoding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ParseError;
+    use {BitSet, BitSetLike};
+
+    #[test]
+    fn round_trip() {
+        let sets: Vec<BitSet> = vec![
+            BitSet::new(),
+            [0].iter().collect(),
+            [16_777_215].iter().collect(),
+            (0..100_000).filter(|i| i % 3 != 0).chain(5_000_000..5_000_500).collect(),
+        ];
+        for set in &sets {
+            let text = set.to_base64();
+            assert!(text.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'));
+            let decoded = BitSet::from_base64(&text).unwrap();
+            assert!((&decoded).iter().eq(set.iter()));
+            assert_eq!(decoded.cached_len(), set.cached_len());
+        }
+        assert_eq!(BitSet::new().to_base64(), "");
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(BitSet::from_base64("not base64!").unwrap_err(), ParseError::InvalidBase64);
+        assert_eq!(BitSet::from_base64("A").unwrap_err(), ParseError::InvalidBase64);
+        assert_eq!(BitSet::from_base64("AAA=").unwrap_err(), ParseError::InvalidBase64);
+        // a gap without a run length
+        assert_eq!(BitSet::from_base64("BA").unwrap_err(), ParseError::InvalidEncoding);
+        // a run of length zero
+        assert_eq!(BitSet::from_base64("BAA").unwrap_err(), ParseError::InvalidEncoding);
+        assert_eq!(ParseError::InvalidEncoding.to_string(), "invalid compact encoding of a bit set");
+    }
+}
@@ -0,0 +1,179 @@
+//! A compact byte encoding of `BitSet`s as runs of consecutive indices.
+//!
+//! A set is encoded as alternating gap and run lengths, each as an
+//! unsigned LEB128 varint. The gap is the number of missing indices since
+//! the end of the previous run (or since zero for the first run), and the
+//! run is the number of consecutive indices in the set that follow it.
+
+use util::*;
+use {BitSet, Ranges};
+
+impl BitSet {
+    /// Encodes the set into a compact sequence of bytes, which can be
+    /// turned back into a set with [`decode_compact`].
+    ///
+    /// Every run of consecutive indices takes up only a few bytes, no
+    /// matter how long it is, so both sparse sets and sets made up of long
+    /// runs encode to far fewer bytes than their words.
+    ///
+    /// [`decode_compact`]: #method.decode_compact
+    pub fn encode_compact(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut end = 0;
+        for range in Ranges::new(self) {
+            write_varint(&mut bytes, range.start() - end);
+            write_varint(&mut bytes, range.end() - range.start() + 1);
+            end = range.end() + 1;
+        }
+        bytes
+    }
+
+    /// Returns the number of bytes [`encode_compact`] would take up for
+    /// this set, without allocating them.
+    ///
+    /// This walks the runs of the set just like encoding does, so it can be
+    /// used to cheaply choose between the compact encoding and the raw
+    /// words.
+    ///
+    /// [`encode_compact`]: #method.encode_compact
+    pub fn rle_size_estimate(&self) -> usize {
+        let mut size = 0;
+        let mut end = 0;
+        for range in Ranges::new(self) {
+            size += varint_len(range.start() - end) + varint_len(range.end() - range.start() + 1);
+            end = range.end() + 1;
+        }
+        size
+    }
+
+    /// Decodes a set encoded with [`encode_compact`].
+    ///
+    /// Returns `None` if `bytes` is truncated, has a run of length zero, or
+    /// holds indices which a `BitSet` can't represent.
+    ///
+    /// [`encode_compact`]: #method.encode_compact
+    pub fn decode_compact(bytes: &[u8]) -> Option<BitSet> {
+        let mut set = BitSet::new();
+        let mut bytes = bytes.iter();
+        let mut end = 0u64;
+        while !bytes.as_slice().is_empty() {
+            let start = end + read_varint(&mut bytes)?;
+            let run = read_varint(&mut bytes)?;
+            end = start + run;
+            if run == 0 || end > MAX_EID as u64 {
+                return None;
+            }
+            set.add_range(start as Index..end as Index);
+        }
+        Some(set)
+    }
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: Index) {
+    while value >= 0x80 {
+        bytes.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// The number of bytes `write_varint` uses for `value`.
+fn varint_len(value: Index) -> usize {
+    let bits = 32 - (value | 1).leading_zeros() as usize;
+    1 + (bits - 1) / 7
+}
+
+/// Reads a varint, returning `None` if it's truncated or doesn't fit in an
+/// `Index`.
+fn read_varint(bytes: &mut ::std::slice::Iter<u8>) -> Option<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes.next()?;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+        if shift >= 32 {
+            return None;
+        }
+    }
+    if value > u64::from(Index::MAX) {
+        return None;
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, weak_rng};
+    use {BitSet, BitSetLike};
+
+    fn round_trip(set: &BitSet) -> BitSet {
+        BitSet::decode_compact(&set.encode_compact()).unwrap()
+    }
+
+    #[test]
+    fn round_trip_sets() {
+        let mut rng = weak_rng();
+        let sets: Vec<BitSet> = vec![
+            BitSet::new(),
+            [0].iter().collect(),
+            [16_777_215].iter().collect(),
+            (0..100_000).collect(),
+            (0..100_000).filter(|i| i % 3 != 0).chain(5_000_000..5_000_500).collect(),
+            (0..1_000).map(|_| rng.gen_range(0, 16_777_216)).collect(),
+        ];
+        for set in &sets {
+            let decoded = round_trip(set);
+            assert!(set.iter().eq((&decoded).iter()));
+            assert_eq!(decoded.cached_len(), set.cached_len());
+        }
+    }
+
+    #[test]
+    fn sparse_is_small() {
+        let set: BitSet = (0..100).map(|i| i * 100_003).collect();
+        let raw = set.layer_lengths()[0] * ::std::mem::size_of::<usize>();
+        assert!(set.encode_compact().len() * 100 < raw);
+
+        let dense: BitSet = (1_000..900_000).collect();
+        assert!(dense.encode_compact().len() <= 8);
+    }
+
+    #[test]
+    fn size_estimate() {
+        let contiguous: BitSet = (1_000..900_000).collect();
+        assert!(contiguous.rle_size_estimate() <= 8);
+
+        let checkerboard: BitSet = (0..100_000).filter(|i| i % 2 == 0).collect();
+        assert!(checkerboard.rle_size_estimate() >= 100_000);
+
+        let mut rng = weak_rng();
+        let sets: Vec<BitSet> = vec![
+            BitSet::new(),
+            contiguous,
+            checkerboard,
+            [0, 127, 128, 16_384, 16_777_215].iter().collect(),
+            (0..1_000).map(|_| rng.gen_range(0, 16_777_216)).collect(),
+        ];
+        for set in &sets {
+            assert_eq!(set.rle_size_estimate(), set.encode_compact().len());
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(BitSet::decode_compact(&[]).unwrap().is_empty());
+        // truncated varint
+        assert!(BitSet::decode_compact(&[0x80]).is_none());
+        // missing run length
+        assert!(BitSet::decode_compact(&[4]).is_none());
+        // zero length run
+        assert!(BitSet::decode_compact(&[4, 0]).is_none());
+        // past the maximum index
+        assert!(BitSet::decode_compact(&[0xff, 0xff, 0xff, 0x07, 2]).is_none());
+        assert!(BitSet::decode_compact(&[0xff, 0xff, 0xff, 0xff, 0x7f, 1]).is_none());
+    }
+}
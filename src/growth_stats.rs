@@ -0,0 +1,24 @@
+//! A process wide count of the reallocations of `BitSet` layers, for
+//! diagnosing how often sets grow while indices are added.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static GROWTH_EVENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Records the growth of a layer vector, if its capacity changed.
+#[inline]
+pub(crate) fn record(old_capacity: usize, new_capacity: usize) {
+    if old_capacity != new_capacity {
+        GROWTH_EVENTS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns the number of times the layers of any `BitSet` had to be
+/// reallocated to make room for an added index.
+///
+/// Every layer counts on its own, so an add which grows several layers
+/// counts several times. The count is shared between all sets and threads,
+/// so take the difference of two calls to measure a piece of code.
+pub fn growth_events() -> usize {
+    GROWTH_EVENTS.load(Ordering::Relaxed)
+}
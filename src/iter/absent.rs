@@ -0,0 +1,97 @@
+use std::ops::Range;
+
+use util::*;
+use {clamp_range, BitSetLike};
+
+/// An `Iterator` over the indices within a range which are not in a
+/// [`BitSetLike`] structure.
+///
+/// Each word of layer 0 overlapping the range is inverted and masked to
+/// the range directly, so unlike iterating over a `BitSetNot` no upper
+/// layers are consulted.
+///
+/// [`BitSetLike`]: ../trait.BitSetLike.html
+#[derive(Clone, Debug)]
+pub struct AbsentBitIter<T> {
+    set: T,
+    first: usize,
+    last: usize,
+    idx: usize,
+    word: usize,
+}
+
+impl<T: BitSetLike> AbsentBitIter<T> {
+    /// Creates a new `AbsentBitIter`. You usually don't call this function
+    /// but just [`.iter_absent_in_range()`] on a bit set.
+    ///
+    /// [`.iter_absent_in_range()`]: ../trait.BitSetLike.html#method.iter_absent_in_range
+    pub fn new(set: T, range: Range<Index>) -> Self {
+        match clamp_range(range) {
+            Some((first, last)) => {
+                let mut iter = AbsentBitIter {
+                    set,
+                    first,
+                    last,
+                    idx: first >> BITS,
+                    word: 0,
+                };
+                iter.word = iter.absent(iter.idx);
+                iter
+            }
+            None => AbsentBitIter {
+                set,
+                first: 0,
+                last: 0,
+                idx: 0,
+                word: 0,
+            },
+        }
+    }
+
+    /// Returns the bits of word `idx` of layer 0 which are missing from
+    /// the set, masked to the range.
+    fn absent(&self, idx: usize) -> usize {
+        let mut word = !self.set.layer0(idx);
+        if idx == self.first >> BITS {
+            word &= !0 << (self.first & ((1 << BITS) - 1));
+        }
+        if idx == self.last >> BITS {
+            word &= !0 >> ((1 << BITS) - 1 - (self.last & ((1 << BITS) - 1)));
+        }
+        word
+    }
+}
+
+impl<T: BitSetLike> Iterator for AbsentBitIter<T> {
+    type Item = Index;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.word != 0 {
+                let bit = self.word.trailing_zeros() as usize;
+                self.word &= !(1 << bit);
+                return Some(((self.idx << BITS) | bit) as Index);
+            }
+            if self.idx >= self.last >> BITS {
+                return None;
+            }
+            self.idx += 1;
+            self.word = self.absent(self.idx);
+        }
+    }
+}
+
+#[test]
+fn absent_in_range() {
+    use {BitSet, BitSetAnd};
+    let set: BitSet = (0..10_000).filter(|i| i % 3 != 0).chain(200_000..200_100).collect();
+    for range in &[0..0, 0..1, 5..7, 60..200, 64..128, 9_000..200_050, 199_990..300_000] {
+        assert!(set.iter_absent_in_range(range.clone()).eq(range.clone().filter(|&i| !set.contains(i))));
+    }
+    assert_eq!(set.iter_absent_in_range(200_000..200_100).count(), 0);
+    assert_eq!(set.iter_absent_in_range(16_777_200..u32::MAX).count(), 16);
+
+    let other: BitSet = (0..100).collect();
+    let and = BitSetAnd(&set, &other);
+    assert!(and.iter_absent_in_range(90..110).eq((90..110).filter(|&i| !and.contains(i))));
+}
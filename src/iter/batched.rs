@@ -0,0 +1,59 @@
+use util::*;
+use iter::BitIter;
+use BitSetLike;
+
+/// An `Iterator` over a [`BitSetLike`] structure, which yields its
+/// indices in batches.
+///
+/// Every batch holds the configured number of indices, except for the
+/// last one which may be shorter.
+///
+/// [`BitSetLike`]: ../trait.BitSetLike.html
+#[derive(Clone, Debug)]
+pub struct BatchedBitIter<T> {
+    iter: BitIter<T>,
+    size: usize,
+}
+
+impl<T> BatchedBitIter<T> {
+    /// Creates a new `BatchedBitIter`. You usually don't call this
+    /// function but just [`.iter_batched()`] on a bit set.
+    ///
+    /// Panics if `size` is zero.
+    ///
+    /// [`.iter_batched()`]: ../trait.BitSetLike.html#method.iter_batched
+    pub fn new(iter: BitIter<T>, size: usize) -> Self {
+        assert!(size > 0, "Expected a batch size above zero");
+        BatchedBitIter { iter, size }
+    }
+}
+
+impl<T> Iterator for BatchedBitIter<T>
+    where T: BitSetLike
+{
+    type Item = Vec<Index>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let batch = self.iter.by_ref().take(self.size).collect::<Vec<_>>();
+        if batch.is_empty() {
+            None
+        } else {
+            Some(batch)
+        }
+    }
+}
+
+#[test]
+fn batch_sizes() {
+    use BitSet;
+    let set: BitSet = (0..10_000).filter(|i| i % 3 == 0).collect();
+    let batches = (&set).iter_batched(100).collect::<Vec<_>>();
+    assert_eq!(batches.len(), 34);
+    assert!(batches[..33].iter().all(|batch| batch.len() == 100));
+    assert_eq!(batches[33].len(), 34);
+    assert_eq!(
+        batches.into_iter().flatten().collect::<Vec<_>>(),
+        set.iter().collect::<Vec<_>>()
+    );
+    assert_eq!(BitSet::new().iter_batched(5).count(), 0);
+}
@@ -0,0 +1,122 @@
+use std::slice::Chunks;
+
+use util::*;
+use BitSet;
+
+/// An `Iterator` over the non-empty blocks of a [`BitSet`], where each
+/// block is the run of layer 0 words covered by a single word of layer 1.
+///
+/// Each item is the index of the layer 1 word, together with the slice of
+/// layer 0 words below it. Only the last block of the set can be shorter
+/// than `usize_bits` words.
+///
+/// [`BitSet`]: ../struct.BitSet.html
+#[derive(Clone, Debug)]
+pub struct Layer1Blocks<'a> {
+    set: &'a BitSet,
+    idx: usize,
+}
+
+impl<'a> Layer1Blocks<'a> {
+    /// Creates a new `Layer1Blocks`. You usually don't call this function
+    /// but just [`.layer1_blocks()`] on a bit set.
+    ///
+    /// [`.layer1_blocks()`]: ../struct.BitSet.html#method.layer1_blocks
+    pub fn new(set: &'a BitSet) -> Self {
+        Layer1Blocks { set, idx: 0 }
+    }
+}
+
+impl<'a> Iterator for Layer1Blocks<'a> {
+    type Item = (usize, &'a [usize]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.idx < self.set.layer1.len() {
+            let idx = self.idx;
+            self.idx += 1;
+            if self.set.layer1[idx] != 0 {
+                let start = idx << BITS;
+                let end = ::std::cmp::min(start + (1 << BITS), self.set.layer0.len());
+                return Some((idx, &self.set.layer0[start..end]));
+            }
+        }
+        None
+    }
+}
+
+/// An `Iterator` over all words of layer 0 of a [`BitSet`], in aligned
+/// chunks of a fixed number of words.
+///
+/// Each item is the index of the first word of the chunk, together with
+/// the words in it. Unlike [`Layer1Blocks`], empty chunks are yielded as
+/// well, and only the last chunk can be shorter than the others.
+///
+/// [`BitSet`]: ../struct.BitSet.html
+/// [`Layer1Blocks`]: struct.Layer1Blocks.html
+#[derive(Clone, Debug)]
+pub struct AlignedWordChunks<'a> {
+    chunks: Chunks<'a, usize>,
+    idx: usize,
+}
+
+impl<'a> AlignedWordChunks<'a> {
+    /// Creates a new `AlignedWordChunks`. You usually don't call this
+    /// function but just [`.aligned_word_chunks()`] on a bit set.
+    ///
+    /// Panics if `chunk` is zero.
+    ///
+    /// [`.aligned_word_chunks()`]: ../struct.BitSet.html#method.aligned_word_chunks
+    pub fn new(set: &'a BitSet, chunk: usize) -> Self {
+        assert!(chunk > 0, "Expected a chunk size above zero");
+        AlignedWordChunks {
+            chunks: set.layer0.chunks(chunk),
+            idx: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for AlignedWordChunks<'a> {
+    type Item = (usize, &'a [usize]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let words = self.chunks.next()?;
+        let idx = self.idx;
+        self.idx += words.len();
+        Some((idx, words))
+    }
+}
+
+#[test]
+fn aligned_chunks() {
+    let set: BitSet = (0..1_000).filter(|i| i % 5 == 0).chain(100_000..100_010).collect();
+    let mut words = Vec::new();
+    for (idx, chunk) in set.aligned_word_chunks(16) {
+        assert_eq!(idx, words.len());
+        assert!(chunk.len() == 16 || idx + chunk.len() == set.layer0.len());
+        words.extend_from_slice(chunk);
+    }
+    assert_eq!(words, set.layer0);
+    assert_eq!(set.aligned_word_chunks(1).count(), set.layer0.len());
+    assert_eq!(BitSet::new().aligned_word_chunks(8).count(), 0);
+}
+
+#[test]
+fn reconstruct() {
+    use BitSetLike;
+    let set: BitSet = (0..10_000).filter(|i| i % 7 == 0).chain(1_000_000..1_000_100).collect();
+    let mut indices = Vec::new();
+    for (block, words) in set.layer1_blocks() {
+        assert!(words.iter().any(|&word| word != 0));
+        for (offset, &word) in words.iter().enumerate() {
+            for bit in 0..(1 << BITS) {
+                if word & (1 << bit) != 0 {
+                    let idx = (((block << BITS) + offset) << BITS) | bit;
+                    indices.push(idx as Index);
+                }
+            }
+        }
+    }
+    assert!(indices.iter().cloned().eq((&set).iter()));
+    assert_eq!(set.layer1_blocks().count(), 3 + 1);
+    assert_eq!(BitSet::new().layer1_blocks().count(), 0);
+}
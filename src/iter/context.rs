@@ -0,0 +1,49 @@
+use util::*;
+use iter::BitIter;
+use BitSetLike;
+
+/// An `Iterator` over a [`BitSetLike`] structure, which yields every index
+/// together with its position in layer 0.
+///
+/// Each item is `(index, word_index, bit)`, where `word_index` is the word
+/// of layer 0 holding the index and `bit` is its bit within that word, so
+/// that `index == word_index * usize_bits + bit`.
+///
+/// [`BitSetLike`]: ../trait.BitSetLike.html
+#[derive(Clone, Debug)]
+pub struct ContextBitIter<T> {
+    iter: BitIter<T>,
+}
+
+impl<T> ContextBitIter<T> {
+    /// Creates a new `ContextBitIter`. You usually don't call this
+    /// function but just [`.iter_with_context()`] on a bit set.
+    ///
+    /// [`.iter_with_context()`]: ../trait.BitSetLike.html#method.iter_with_context
+    pub fn new(iter: BitIter<T>) -> Self {
+        ContextBitIter { iter }
+    }
+}
+
+impl<T> Iterator for ContextBitIter<T>
+    where T: BitSetLike
+{
+    type Item = (Index, usize, u32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|i| (i, i.offset(SHIFT1), i.row(SHIFT0) as u32))
+    }
+}
+
+#[test]
+fn reassembles() {
+    use BitSet;
+    let set: BitSet = [0, 63, 64, 4_097, 1_000_000].iter().collect();
+    let items = (&set).iter_with_context().collect::<Vec<_>>();
+    assert_eq!(items[1], (63, 0, 63));
+    assert_eq!(items[2], (64, 1, 0));
+    for &(i, word, bit) in &items {
+        assert_eq!(i, ((word << BITS) as Index) | bit);
+    }
+    assert!(items.iter().map(|&(i, _, _)| i).eq(set.iter()));
+}
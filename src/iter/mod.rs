@@ -1,25 +1,58 @@
+use std::iter::FusedIterator;
+
 use util::*;
 use {BitSet, BitSetLike};
 
+pub use self::absent::AbsentBitIter;
+pub use self::batched::BatchedBitIter;
+pub use self::blocks::{AlignedWordChunks, Layer1Blocks};
+pub use self::context::ContextBitIter;
 pub use self::drain::DrainBitIter;
+pub use self::offset::OffsetBitIter;
+pub use self::pairs::PairBitIter;
+pub use self::ranges::MinLengthRuns;
+pub(crate) use self::ranges::Ranges;
+pub use self::take_while::TakeWhileBitIter;
+pub use self::windows::Windows;
 
 #[cfg(feature="parallel")]
 pub use self::parallel::{BitParIter, BitProducer};
 
 #[cfg(feature="parallel")]
 mod parallel;
+mod absent;
+mod batched;
+mod blocks;
+mod context;
 mod drain;
+mod offset;
+mod pairs;
+mod ranges;
+mod take_while;
+mod windows;
 
 /// An `Iterator` over a [`BitSetLike`] structure.
 ///
+/// Iterators over cloneable sets, such as `&BitSet`, can be cloned
+/// cheaply to save the current position and resume from it later.
+///
 /// [`BitSetLike`]: ../trait.BitSetLike.html
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct BitIter<T> {
     pub(crate) set: T,
     pub(crate) masks: [usize; LAYERS],
     pub(crate) prefix: [u32; LAYERS - 1],
 }
 
+/// The owning `Iterator` of a [`BitSet`], returned by its `IntoIterator`
+/// implementation.
+///
+/// It holds the set by value, so `for i in set { .. }` moves the set into
+/// the loop, and its allocation is freed once the iterator is dropped.
+///
+/// [`BitSet`]: ../struct.BitSet.html
+pub type IntoBitIter = BitIter<BitSet>;
+
 impl<T> BitIter<T> {
     /// Creates a new `BitIter`. You usually don't call this function
     /// but just [`.iter()`] on a bit set.
@@ -39,6 +72,34 @@ impl<T: BitSetLike> BitIter<T> {
     pub fn contains(&self, i: Index) -> bool {
         self.set.contains(i)
     }
+
+    /// Returns an estimate of the number of indices left to iterate over,
+    /// without loading any more words.
+    ///
+    /// The hint counts the remaining bits of the current word exactly, and
+    /// every unvisited word of the upper layers as a single index. For a
+    /// `BitSet`, where every set bit of the upper layers has an index below
+    /// it, this is a lower bound which becomes exact in the final word.
+    /// The upper layers of composite sets may claim words that are empty,
+    /// so for those the hint can also be too high.
+    pub fn remaining_hint(&self) -> usize {
+        self.masks.iter().map(|mask| mask.count_ones() as usize).sum()
+    }
+
+    /// Fills `out` with the next indices of the iterator, returning how
+    /// many were written.
+    ///
+    /// Fewer than `out.len()` indices are only written once the iterator
+    /// is exhausted, so the iterator can be kept as a cursor to drain a
+    /// large set into a fixed buffer over several calls.
+    pub fn drain_into_slice(&mut self, out: &mut [Index]) -> usize {
+        let mut written = 0;
+        for (slot, index) in out.iter_mut().zip(self) {
+            *slot = index;
+            written += 1;
+        }
+        written
+    }
 }
 
 impl<'a> BitIter<&'a mut BitSet> {
@@ -48,6 +109,9 @@ impl<'a> BitIter<&'a mut BitSet> {
         while let Some(level) = (1..LAYERS).find(|&level| self.handle_level(level) == Continue) {
             let lower = level - 1;
             let idx = (self.prefix[lower] >> BITS) as usize;
+            if lower == 0 {
+                self.set.len -= self.set.layer0(idx).count_ones() as usize;
+            }
             *self.set.layer_mut(lower, idx) = 0;
             if level == LAYERS - 1 {
                 self.set.layer3 &= !((2 << idx) - 1);
@@ -84,7 +148,30 @@ impl<T> Iterator for BitIter<T>
     }
 }
 
+// `next` only returns `None` once the masks of every level are empty, and
+// words are only loaded below a set bit of a mask, so nothing is ever
+// loaded again after that.
+impl<T> FusedIterator for BitIter<T> where T: BitSetLike {}
+
 impl<T: BitSetLike> BitIter<T> {
+    /// Advances the iterator to the next non-empty word of layer 0,
+    /// returning the index of the word together with its value.
+    ///
+    /// This uses the same descent as bit-wise iteration, but hands out
+    /// whole words instead of inspecting their bits one at a time.
+    pub(crate) fn next_word(&mut self) -> Option<(usize, usize)> {
+        use self::State::Continue;
+        while let Some(level) = (1..LAYERS).find(|&level| self.handle_level(level) == Continue) {
+            if level == 1 {
+                let word = ::std::mem::replace(&mut self.masks[0], 0);
+                if word != 0 {
+                    return Some(((self.prefix[0] >> BITS) as usize, word));
+                }
+            }
+        }
+        None
+    }
+
     pub(crate) fn handle_level(&mut self, level: usize) -> State {
         use self::State::*;
         if self.masks[level] == 0 {
@@ -134,4 +221,136 @@ mod tests {
             assert_eq!(0, i);
         }
     }
+
+    #[test]
+    fn remaining_hint() {
+        let set: BitSet = (0..5_000).filter(|i| i % 3 == 0).chain(1_000_000..1_000_010).collect();
+        let mut iter = (&set).iter();
+        let mut remaining = set.cached_len();
+        assert!(iter.remaining_hint() <= remaining);
+        while iter.next().is_some() {
+            remaining -= 1;
+            assert!(iter.remaining_hint() <= remaining);
+            if remaining < 10 {
+                assert_eq!(iter.remaining_hint(), remaining);
+            }
+        }
+        assert_eq!(iter.remaining_hint(), 0);
+    }
+
+    #[test]
+    fn into_iter_owned() {
+        use super::IntoBitIter;
+
+        let set: BitSet = (0..1_000).filter(|i| i % 3 == 0).chain(Some(500_000)).collect();
+        let expected = (&set).iter().collect::<Vec<_>>();
+        let iter: IntoBitIter = set.into_iter();
+        assert!(iter.eq(expected.iter().cloned()));
+
+        let set: BitSet = expected.iter().collect();
+        let mut collected = Vec::new();
+        for i in set {
+            collected.push(i);
+        }
+        assert_eq!(collected, expected);
+    }
+
+    #[test]
+    fn repeated_words() {
+        use util::{Index, BITS};
+
+        // The same words are handed out for every index of a layer, so the
+        // position of a word can only be told apart through the prefix.
+        struct Repeated;
+
+        impl BitSetLike for Repeated {
+            fn layer3(&self) -> usize {
+                0b11
+            }
+            fn layer2(&self, _: usize) -> usize {
+                0b101
+            }
+            fn layer1(&self, _: usize) -> usize {
+                0b11 | 1 << ((1 << BITS) - 1)
+            }
+            fn layer0(&self, _: usize) -> usize {
+                0b1001 | 1 << ((1 << BITS) - 1)
+            }
+            fn contains(&self, i: Index) -> bool {
+                let bit = |word: usize, shift: usize| word >> ((i as usize >> shift) & ((1 << BITS) - 1)) & 1 == 1;
+                bit(self.layer3(), 3 * BITS) && bit(self.layer2(0), 2 * BITS)
+                    && bit(self.layer1(0), BITS) && bit(self.layer0(0), 0)
+            }
+        }
+
+        let bits = |word: usize| (0..1 << BITS).filter(move |b| word >> b & 1 == 1);
+        let mut expected = Vec::new();
+        for p3 in bits(Repeated.layer3()) {
+            for p2 in bits(Repeated.layer2(0)) {
+                for p1 in bits(Repeated.layer1(0)) {
+                    for p0 in bits(Repeated.layer0(0)) {
+                        expected.push((((p3 << BITS | p2) << BITS | p1) << BITS | p0) as Index);
+                    }
+                }
+            }
+        }
+        assert_eq!(expected.len(), 2 * 2 * 3 * 3);
+
+        let yielded = Repeated.iter().collect::<Vec<_>>();
+        assert_eq!(yielded, expected);
+        assert!(yielded.iter().all(|&i| Repeated.contains(i)));
+
+        let mut iter = Repeated.iter();
+        let mut words = Vec::new();
+        while let Some((idx, word)) = iter.next_word() {
+            assert_eq!(word, Repeated.layer0(0));
+            words.push(idx);
+        }
+        assert!(words.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(words.len(), 2 * 2 * 3);
+    }
+
+    #[test]
+    fn fused() {
+        use std::iter::FusedIterator;
+        use util::{Index, MAX_EID};
+        use {BitSetAnd, BitSetNot};
+
+        fn assert_fused<I: FusedIterator>(_: &I) {}
+
+        let set: BitSet = (0..1_000).filter(|i| i % 3 == 0).chain(Some(MAX_EID as Index - 1)).collect();
+        let mut iter = (&set).iter();
+        assert_fused(&iter);
+        assert_eq!(iter.by_ref().count(), set.cached_len());
+        for _ in 0..100 {
+            assert_eq!(iter.next(), None);
+        }
+        assert_eq!(iter.remaining_hint(), 0);
+
+        // the complement of a small set is only exhausted at the end of
+        // the keyspace.
+        let mut iter = BitSetNot(&set).iter().skip(MAX_EID - set.cached_len() - 5);
+        assert_eq!(iter.by_ref().count(), 5);
+        for _ in 0..100 {
+            assert_eq!(iter.next(), None);
+        }
+
+        let mut iter = BitSetAnd(&set, BitSetNot(&set)).iter();
+        for _ in 0..100 {
+            assert_eq!(iter.next(), None);
+        }
+    }
+
+    #[test]
+    fn iterator_clone_resumes() {
+        let set: BitSet = (0..100_000).filter(|i| i % 7 == 0 || i % 4_099 == 0).collect();
+        let mut iter = (&set).iter();
+        iter.by_ref().take(5_000).for_each(|_| {});
+
+        let saved = iter.clone();
+        let rest = iter.collect::<Vec<_>>();
+        assert!(!rest.is_empty());
+        assert_eq!(saved.clone().collect::<Vec<_>>(), rest);
+        assert_eq!(saved.count(), rest.len());
+    }
 }
@@ -0,0 +1,97 @@
+use std::ops::Range;
+
+use util::*;
+use {clamp_range, BitSetLike};
+
+/// An `Iterator` over the indices of a [`BitSetLike`] structure within a
+/// window, relative to the start of the window.
+///
+/// The words of layer 0 overlapping the window are visited in ascending
+/// order, skipping over the blocks which layer 1 marks as empty.
+///
+/// [`BitSetLike`]: ../trait.BitSetLike.html
+#[derive(Clone, Debug)]
+pub struct OffsetBitIter<T> {
+    set: T,
+    base: Index,
+    first: usize,
+    last: usize,
+    idx: usize,
+    word: usize,
+}
+
+impl<T: BitSetLike> OffsetBitIter<T> {
+    /// Creates a new `OffsetBitIter`. You usually don't call this function
+    /// but just [`.iter_offset()`] on a bit set.
+    ///
+    /// [`.iter_offset()`]: ../trait.BitSetLike.html#method.iter_offset
+    pub fn new(set: T, base: Index, len: Index) -> Self {
+        let window: Range<Index> = base..base.saturating_add(len);
+        let (first, last) = clamp_range(window).unwrap_or((1, 0));
+        let mut iter = OffsetBitIter {
+            set,
+            base,
+            first,
+            last,
+            idx: first >> BITS,
+            word: 0,
+        };
+        if first <= last {
+            iter.word = iter.masked(iter.idx);
+        }
+        iter
+    }
+
+    /// Returns word `idx` of layer 0, masked to the window.
+    fn masked(&self, idx: usize) -> usize {
+        let mut word = self.set.layer0(idx);
+        if idx == self.first >> BITS {
+            word &= !0 << (self.first & ((1 << BITS) - 1));
+        }
+        if idx == self.last >> BITS {
+            word &= !0 >> ((1 << BITS) - 1 - (self.last & ((1 << BITS) - 1)));
+        }
+        word
+    }
+}
+
+impl<T: BitSetLike> Iterator for OffsetBitIter<T> {
+    type Item = Index;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.word != 0 {
+                let bit = self.word.trailing_zeros() as usize;
+                self.word &= !(1 << bit);
+                return Some(((self.idx << BITS) | bit) as Index - self.base);
+            }
+            if self.first > self.last || self.idx >= self.last >> BITS {
+                return None;
+            }
+            self.idx += 1;
+            // skip to the next block of layer 0 words which isn't empty
+            while self.idx & ((1 << BITS) - 1) == 0 && self.set.layer1(self.idx >> BITS) == 0 {
+                self.idx += 1 << BITS;
+                if self.idx > self.last >> BITS {
+                    return None;
+                }
+            }
+            self.word = self.masked(self.idx);
+        }
+    }
+}
+
+#[test]
+fn offset_window() {
+    use {BitSet, BitSetAnd};
+    let set: BitSet = (0..10_000).filter(|i| i % 3 == 0).chain(200_000..200_100).collect();
+    for &(base, len) in &[(0, 0), (0, 10), (5, 1), (60, 200), (9_000, 200_050), (199_990, 300_000), (10, u32::MAX)] {
+        let expected = (&set).iter().filter(|&i| i >= base && i - base < len).map(|i| i - base);
+        assert!(set.iter_offset(base, len).eq(expected), "{} {}", base, len);
+    }
+    assert_eq!(set.iter_offset(200_000, 100).collect::<Vec<_>>(), (0..100).collect::<Vec<_>>());
+
+    let other: BitSet = (0..100).collect();
+    let and = BitSetAnd(&set, &other);
+    assert_eq!(and.iter_offset(10, 20).collect::<Vec<_>>(), [2, 5, 8, 11, 14, 17]);
+}
@@ -0,0 +1,65 @@
+use util::*;
+use iter::BitIter;
+use BitSetLike;
+
+/// An `Iterator` over every pair of distinct indices `(i, j)` with `i < j`
+/// in a [`BitSetLike`] structure.
+///
+/// Pairs are yielded in lexicographic order. The number of pairs grows
+/// quadratically with the number of indices in the set.
+///
+/// [`BitSetLike`]: ../trait.BitSetLike.html
+#[derive(Clone, Debug)]
+pub struct PairBitIter<T> {
+    outer: BitIter<T>,
+    inner: BitIter<T>,
+    current: Option<Index>,
+}
+
+impl<T: Clone> PairBitIter<T> {
+    /// Creates a new `PairBitIter`. You usually don't call this function
+    /// but just [`.iter_pairs()`] on a bit set.
+    ///
+    /// [`.iter_pairs()`]: ../trait.BitSetLike.html#method.iter_pairs
+    pub fn new(iter: BitIter<T>) -> Self {
+        PairBitIter {
+            inner: iter.clone(),
+            outer: iter,
+            current: None,
+        }
+    }
+}
+
+impl<T> Iterator for PairBitIter<T>
+    where T: BitSetLike + Clone
+{
+    type Item = (Index, Index);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(i) = self.current {
+                if let Some(j) = self.inner.next() {
+                    return Some((i, j));
+                }
+            }
+            // the inner iterator resumes right after the new first index
+            self.current = Some(self.outer.next()?);
+            self.inner = self.outer.clone();
+        }
+    }
+}
+
+#[test]
+fn three_pairs() {
+    use BitSet;
+    let set: BitSet = [3, 70, 5_000].iter().collect();
+    assert_eq!(
+        set.iter_pairs().collect::<Vec<_>>(),
+        [(3, 70), (3, 5_000), (70, 5_000)]
+    );
+
+    let set: BitSet = (0..100).collect();
+    assert_eq!(set.iter_pairs().count(), 100 * 99 / 2);
+    assert!(set.iter_pairs().all(|(i, j)| i < j));
+    assert_eq!([7].iter().collect::<BitSet>().iter_pairs().count(), 0);
+}
@@ -0,0 +1,107 @@
+use std::ops::RangeInclusive;
+
+use util::*;
+use iter::BitIter;
+use BitSetLike;
+
+/// An `Iterator` over the maximal runs of consecutive indices in a
+/// [`BitSetLike`] structure.
+///
+/// Runs are coalesced across word boundaries, so a run spanning several
+/// words of layer 0 is yielded as a single range.
+///
+/// [`BitSetLike`]: ../trait.BitSetLike.html
+#[derive(Clone, Debug)]
+pub(crate) struct Ranges<T> {
+    iter: BitIter<T>,
+    word: usize,
+    base: Index,
+    pending: Option<(Index, Index)>,
+}
+
+impl<T: BitSetLike> Ranges<T> {
+    pub(crate) fn new(set: T) -> Self {
+        Ranges {
+            iter: set.iter(),
+            word: 0,
+            base: 0,
+            pending: None,
+        }
+    }
+}
+
+impl<T> Iterator for Ranges<T>
+    where T: BitSetLike
+{
+    type Item = RangeInclusive<Index>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.word == 0 {
+                match self.iter.next_word() {
+                    Some((idx, word)) => {
+                        self.word = word;
+                        self.base = (idx << BITS) as Index;
+                    }
+                    None => return self.pending.take().map(|(start, end)| start..=end),
+                }
+            }
+
+            let first_bit = self.word.trailing_zeros();
+            let run = (self.word >> first_bit).trailing_ones();
+            let last_bit = first_bit + run;
+            self.word = if last_bit as usize >= 1 << BITS {
+                0
+            } else {
+                self.word & (!0 << last_bit)
+            };
+
+            let (start, end) = (self.base | first_bit, self.base | (last_bit - 1));
+            match self.pending {
+                Some((pending, last)) if last + 1 == start => {
+                    self.pending = Some((pending, end));
+                }
+                _ => {
+                    if let Some((s, e)) = self.pending.replace((start, end)) {
+                        return Some(s..=e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// An `Iterator` over the maximal runs of consecutive indices in a
+/// [`BitSetLike`] structure, which skips every run shorter than a minimum
+/// length.
+///
+/// [`BitSetLike`]: ../trait.BitSetLike.html
+#[derive(Clone, Debug)]
+pub struct MinLengthRuns<T> {
+    ranges: Ranges<T>,
+    min: Index,
+}
+
+impl<T: BitSetLike> MinLengthRuns<T> {
+    /// Creates a new `MinLengthRuns`. You usually don't call this function
+    /// but just [`.iter_runs_min_length()`] on a bit set.
+    ///
+    /// [`.iter_runs_min_length()`]: ../trait.BitSetLike.html#method.iter_runs_min_length
+    pub fn new(set: T, min: Index) -> Self {
+        MinLengthRuns {
+            ranges: Ranges::new(set),
+            min,
+        }
+    }
+}
+
+impl<T> Iterator for MinLengthRuns<T>
+    where T: BitSetLike
+{
+    type Item = RangeInclusive<Index>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let min = self.min;
+        self.ranges.find(|range| range.end() - range.start() + 1 >= min)
+    }
+}
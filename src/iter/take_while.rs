@@ -0,0 +1,61 @@
+use std::fmt;
+
+use util::*;
+use iter::BitIter;
+use BitSetLike;
+
+/// An `Iterator` over the indices of a [`BitSetLike`] structure in
+/// ascending order, which stops at the first index rejected by a
+/// predicate.
+///
+/// [`BitSetLike`]: ../trait.BitSetLike.html
+#[derive(Clone)]
+pub struct TakeWhileBitIter<T, F> {
+    iter: BitIter<T>,
+    f: F,
+    done: bool,
+}
+
+impl<T: BitSetLike, F: FnMut(Index) -> bool> TakeWhileBitIter<T, F> {
+    /// Creates a new `TakeWhileBitIter`. You usually don't call this
+    /// function but just [`.iter_while()`] on a bit set.
+    ///
+    /// [`.iter_while()`]: ../trait.BitSetLike.html#method.iter_while
+    pub fn new(set: T, f: F) -> Self {
+        TakeWhileBitIter {
+            iter: set.iter(),
+            f,
+            done: false,
+        }
+    }
+}
+
+impl<T, F> Iterator for TakeWhileBitIter<T, F>
+    where T: BitSetLike,
+          F: FnMut(Index) -> bool
+{
+    type Item = Index;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.iter.next() {
+            Some(i) if (self.f)(i) => Some(i),
+            _ => {
+                self.done = true;
+                None
+            }
+        }
+    }
+}
+
+// Closures don't implement `Debug`, so only the iterator is printed.
+impl<T: fmt::Debug, F> fmt::Debug for TakeWhileBitIter<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TakeWhileBitIter")
+            .field("iter", &self.iter)
+            .field("done", &self.done)
+            .finish()
+    }
+}
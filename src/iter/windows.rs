@@ -0,0 +1,47 @@
+use util::*;
+use {count_in_range, BitSetLike};
+
+/// An `Iterator` over windows of a fixed size sliding over a
+/// [`BitSetLike`] structure, yielding the start of every window together
+/// with the number of indices of the set within it.
+///
+/// [`BitSetLike`]: ../trait.BitSetLike.html
+#[derive(Clone, Debug)]
+pub struct Windows<T> {
+    set: T,
+    size: Index,
+    step: Index,
+    next: Option<Index>,
+}
+
+impl<T: BitSetLike> Windows<T> {
+    /// Creates a new `Windows`. You usually don't call this function but
+    /// just [`.windows()`] on a bit set.
+    ///
+    /// Panics if `step` is zero.
+    ///
+    /// [`.windows()`]: ../trait.BitSetLike.html#method.windows
+    pub fn new(set: T, size: Index, step: Index) -> Self {
+        assert!(step > 0, "Expected a window step above zero");
+        Windows {
+            set,
+            size,
+            step,
+            next: Some(0),
+        }
+    }
+}
+
+impl<T: BitSetLike> Iterator for Windows<T> {
+    type Item = (Index, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.next?;
+        if !self.set.any_in_range(start..MAX_EID as Index) {
+            self.next = None;
+            return None;
+        }
+        self.next = start.checked_add(self.step);
+        Some((start, count_in_range(&self.set, start..start.saturating_add(self.size))))
+    }
+}
@@ -46,22 +46,46 @@
 #![deny(missing_docs)]
 
 extern crate atom;
+#[cfg(feature="base64")]
+extern crate base64;
 #[cfg(feature="parallel")]
 extern crate rayon;
+#[cfg(feature="roaring")]
+extern crate roaring;
 #[cfg(test)]
 extern crate rand;
 
 mod atomic;
+#[cfg(feature="base64")]
+mod base64_string;
+mod compact;
+#[cfg(feature="growth-stats")]
+mod growth_stats;
 mod iter;
 mod ops;
+#[cfg(feature="roaring")]
+mod roaring_bitmap;
 mod util;
+mod wide;
 
 pub use atomic::AtomicBitSet;
-pub use iter::{BitIter, DrainBitIter};
+#[cfg(feature="base64")]
+pub use base64_string::ParseError;
+#[cfg(feature="growth-stats")]
+pub use growth_stats::growth_events;
+pub use iter::{AbsentBitIter, AlignedWordChunks, BatchedBitIter, BitIter, ContextBitIter, DrainBitIter, IntoBitIter, Layer1Blocks, MinLengthRuns, OffsetBitIter, PairBitIter, TakeWhileBitIter, Windows};
 #[cfg(feature="parallel")]
 pub use iter::{BitParIter, BitProducer};
-pub use ops::{BitSetAll, BitSetAnd, BitSetNot, BitSetOr, BitSetXor};
+pub use ops::{BitSetAll, BitSetAnd, BitSetAnd3, BitSetNot, BitSetNotBounded, BitSetOr, BitSetOr3, BitSetXor, FnBitSet};
+pub use wide::{BitSet64, BitSet64Iter};
 
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::ops::{Range, RangeInclusive};
+
+use iter::Ranges;
 use util::*;
 
 /// A `BitSet` is a simple set designed to track which indices are placed
@@ -75,8 +99,51 @@ pub struct BitSet {
     layer2: Vec<usize>,
     layer1: Vec<usize>,
     layer0: Vec<usize>,
+    len: usize,
+    growth: Growth,
+}
+
+/// How a [`BitSet`] grows its layers when an index beyond their end is
+/// added. See [`BitSet::set_growth`].
+///
+/// [`BitSet`]: struct.BitSet.html
+/// [`BitSet::set_growth`]: struct.BitSet.html#method.set_growth
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Growth {
+    /// At least doubles the allocated capacity whenever a layer has to
+    /// grow, so a sequence of increasing adds only reallocates a
+    /// logarithmic number of times. This is the default.
+    #[default]
+    Geometric,
+    /// Grows each layer to exactly the number of words needed. This wastes
+    /// no memory, but reallocates every time a layer grows.
+    Exact,
+}
+
+/// The error returned by [`BitSet::contains_checked`] for an index which a
+/// `BitSet` can't represent.
+///
+/// [`BitSet::contains_checked`]: struct.BitSet.html#method.contains_checked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CapacityError {
+    index: Index,
+}
+
+impl CapacityError {
+    /// Returns the index which was out of range.
+    pub fn index(&self) -> Index {
+        self.index
+    }
+}
+
+impl fmt::Display for CapacityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "index {} is out of range, indices must be less than {}", self.index, MAX_EID)
+    }
 }
 
+impl Error for CapacityError {}
+
 impl BitSet {
     /// Creates an empty `BitSet`.
     pub fn new() -> BitSet {
@@ -98,14 +165,57 @@ impl BitSet {
         value
     }
 
+    /// Sets how the layers grow when indices beyond their end are added.
+    ///
+    /// This only affects future growth, the current allocation is kept.
+    pub fn set_growth(&mut self, growth: Growth) {
+        self.growth = growth;
+    }
+
+    /// Creates a `BitSet` from the words of layer 0, deriving the upper
+    /// layers from them.
+    ///
+    /// Bit `b` of word `w` represents the index `w * usize_bits + b`.
+    /// Panics if there are more words than needed to represent the
+    /// maximum index.
+    pub fn from_layer0(words: Vec<usize>) -> BitSet {
+        if words.len() > MAX_EID >> BITS {
+            panic!("Expected at most {} words, found {}", MAX_EID >> BITS, words.len());
+        }
+        let mut value = BitSet {
+            layer0: words,
+            ..BitSet::new()
+        };
+        value.rebuild_upper_layers();
+        value
+    }
+
+    /// Creates a `BitSet` from the indices of `iter`, skipping the indices
+    /// which are too large to be represented instead of panicking.
+    pub fn from_iter_bounded<I: IntoIterator<Item = Index>>(iter: I) -> BitSet {
+        iter.into_iter().filter(|&id| (id as usize) < MAX_EID).collect()
+    }
+
     #[inline(never)]
     fn extend(&mut self, id: Index) {
         Self::valid_range(id);
         let (p0, p1, p2) = offsets(id);
 
-        Self::fill_up(&mut self.layer2, p2);
-        Self::fill_up(&mut self.layer1, p1);
-        Self::fill_up(&mut self.layer0, p0);
+        let growth = self.growth;
+        Self::grow(&mut self.layer2, p2, growth);
+        Self::grow(&mut self.layer1, p1, growth);
+        Self::grow(&mut self.layer0, p0, growth);
+    }
+
+    fn grow(vec: &mut Vec<usize>, upper_index: usize, growth: Growth) {
+        #[cfg(feature="growth-stats")]
+        let capacity = vec.capacity();
+        if growth == Growth::Exact && vec.len() <= upper_index {
+            vec.reserve_exact(upper_index + 1 - vec.len());
+        }
+        Self::fill_up(vec, upper_index);
+        #[cfg(feature="growth-stats")]
+        growth_stats::record(capacity, vec.capacity());
     }
 
     fn fill_up(vec: &mut Vec<usize>, upper_index: usize) {
@@ -119,13 +229,214 @@ impl BitSet {
     #[inline(never)]
     fn add_slow(&mut self, id: Index) {
         let (_, p1, p2) = offsets(id);
+        debug_assert!(
+            p1 < self.layer1.len() && p2 < self.layer2.len(),
+            "Upper layers are not allocated for index {}, layer lengths are {:?}",
+            id, self.layer_lengths()
+        );
         self.layer1[p1] |= id.mask(SHIFT1);
         self.layer2[p2] |= id.mask(SHIFT2);
         self.layer3 |= id.mask(SHIFT3);
     }
 
+    /// Discards layers 1 to 3, and the cached length, and recomputes them
+    /// from layer 0.
+    ///
+    /// The set always keeps its layers consistent by itself, so this is
+    /// only needed to repair a set whose hierarchy is suspected to be out
+    /// of sync with layer 0, for example after handing its words to
+    /// foreign code.
+    pub fn rebuild_upper_layers(&mut self) {
+        for word in self.layer1.iter_mut().chain(self.layer2.iter_mut()) {
+            *word = 0;
+        }
+        if !self.layer0.is_empty() {
+            let p1 = (self.layer0.len() - 1) >> BITS;
+            Self::fill_up(&mut self.layer1, p1);
+            Self::fill_up(&mut self.layer2, p1 >> BITS);
+        }
+        self.layer3 = 0;
+        self.len = 0;
+
+        for (i, &word) in self.layer0.iter().enumerate() {
+            if word != 0 {
+                self.len += word.count_ones() as usize;
+                self.layer1[i >> BITS] |= 1 << (i & ((1 << BITS) - 1));
+            }
+        }
+        for (i, &word) in self.layer1.iter().enumerate() {
+            if word != 0 {
+                self.layer2[i >> BITS] |= 1 << (i & ((1 << BITS) - 1));
+            }
+        }
+        for (i, &word) in self.layer2.iter().enumerate() {
+            if word != 0 {
+                self.layer3 |= 1 << i;
+            }
+        }
+    }
+
+    /// Sets the bits of `mask` in word `idx` of layer 0, which must be
+    /// allocated, and marks the word in the upper layers.
+    fn set_word_bits(&mut self, idx: usize, mask: usize) {
+        let old = self.layer0[idx];
+        if mask & !old == 0 {
+            return;
+        }
+        self.layer0[idx] = old | mask;
+        self.len += (mask & !old).count_ones() as usize;
+        if old == 0 {
+            self.add_slow((idx << BITS) as Index);
+        }
+    }
+
+    /// Clears the bits of `mask` from word `idx` of layer 0, which must be
+    /// allocated. The upper layers are cleared the same way as in
+    /// `remove`, so only the layers that became empty are touched.
+    fn clear_word_bits(&mut self, idx: usize, mask: usize) {
+        let old = self.layer0[idx];
+        if old & mask == 0 {
+            return;
+        }
+        self.layer0[idx] = old & !mask;
+        self.len -= (old & mask).count_ones() as usize;
+        if self.layer0[idx] != 0 {
+            return;
+        }
+
+        let (p1, p2) = (idx >> BITS, idx >> (2 * BITS));
+        self.layer1[p1] &= !(1 << (idx & ((1 << BITS) - 1)));
+        if self.layer1[p1] != 0 {
+            return;
+        }
+
+        self.layer2[p2] &= !(1 << (p1 & ((1 << BITS) - 1)));
+        if self.layer2[p2] != 0 {
+            return;
+        }
+
+        self.layer3 &= !(1 << p2);
+    }
+
+    /// Recomputes the bits of layers 1 to 3 which summarize the words
+    /// `first..=last` of layer 0. The upper layers must already be large
+    /// enough to hold them.
+    fn refresh_upper_layers(&mut self, first: usize, last: usize) {
+        for p1 in (first >> BITS)..((last >> BITS) + 1) {
+            self.layer1[p1] = Self::summarize(&self.layer0, p1);
+        }
+        for p2 in (first >> (2 * BITS))..((last >> (2 * BITS)) + 1) {
+            self.layer2[p2] = Self::summarize(&self.layer1, p2);
+            if self.layer2[p2] == 0 {
+                self.layer3 &= !(1 << p2);
+            } else {
+                self.layer3 |= 1 << p2;
+            }
+        }
+    }
+
+    /// Builds the word of the layer above `layer` at `idx`, which has a
+    /// bit set for each non-empty word below it.
+    fn summarize(layer: &[usize], idx: usize) -> usize {
+        layer.iter()
+            .skip(idx << BITS)
+            .take(1 << BITS)
+            .enumerate()
+            .filter(|&(_, &word)| word != 0)
+            .fold(0, |summary, (bit, _)| summary | (1 << bit))
+    }
+
+    /// Creates the union of all `sets`.
+    ///
+    /// Every non-empty word of the inputs is merged into the result
+    /// directly, and the hierarchy above layer 0 is only built once at the
+    /// end. This is cheaper than repeatedly merging sets pairwise.
+    pub fn union_all(sets: &[&BitSet]) -> BitSet {
+        let words = sets.iter().map(|set| set.layer0.len()).max().unwrap_or(0);
+        let mut union = BitSet {
+            layer0: vec![0; words],
+            ..BitSet::new()
+        };
+        for set in sets {
+            let mut iter = set.iter();
+            while let Some((idx, word)) = iter.next_word() {
+                union.layer0[idx] |= word;
+            }
+        }
+        union.rebuild_upper_layers();
+        union
+    }
+
+    /// Creates the intersection of all `sets`.
+    ///
+    /// The non-empty words of the smallest input are intersected with the
+    /// other inputs one at a time, moving on to the next word as soon as
+    /// it becomes zero. The intersection of no sets at all is empty.
+    pub fn intersect_all(sets: &[&BitSet]) -> BitSet {
+        let smallest = match sets.iter().min_by_key(|set| set.layer0.len()) {
+            Some(smallest) => smallest,
+            None => return BitSet::new(),
+        };
+        let mut intersection = BitSet {
+            layer0: vec![0; smallest.layer0.len()],
+            ..BitSet::new()
+        };
+        let mut iter = smallest.iter();
+        while let Some((idx, mut word)) = iter.next_word() {
+            for set in sets {
+                word &= set.layer0[idx];
+                if word == 0 {
+                    break;
+                }
+            }
+            intersection.layer0[idx] = word;
+        }
+        intersection.rebuild_upper_layers();
+        intersection
+    }
+
+    /// Replaces the contents of this set with the members of `source`,
+    /// reusing the allocation of this set.
+    ///
+    /// Only layer 0 of `source` is read, and the rest of the hierarchy is
+    /// rebuilt from it. If `source` is written to concurrently, the result
+    /// is a best-effort snapshot which may or may not include the bits
+    /// that were added while copying.
+    pub fn copy_from_atomic(&mut self, source: &AtomicBitSet) {
+        self.clear();
+        let mut iter = source.iter();
+        while let Some((idx, word)) = iter.next_word() {
+            Self::fill_up(&mut self.layer0, idx);
+            self.layer0[idx] = word;
+        }
+        self.rebuild_upper_layers();
+    }
+
+    /// Merges `value` into word `word_index` of layer 0, growing the set
+    /// as needed.
+    ///
+    /// Bit `b` of the word represents the index `word_index * usize_bits + b`.
+    /// The upper layers are updated accordingly if `value` is not zero.
+    pub fn set_word(&mut self, word_index: usize, value: usize) {
+        if word_index >= MAX_EID >> BITS {
+            panic!("Expected word index to be less then {}, found {}", MAX_EID >> BITS, word_index);
+        }
+        if value == 0 {
+            return;
+        }
+        if word_index >= self.layer0.len() {
+            self.extend((word_index << BITS) as Index);
+        }
+        self.set_word_bits(word_index, value);
+    }
+
     /// Adds `id` to the `BitSet`. Returns `true` if the value was
     /// already in the set.
+    ///
+    /// Note that this is the opposite of [`insert`], which follows the
+    /// convention of the standard collections.
+    ///
+    /// [`insert`]: #method.insert
     #[inline]
     pub fn add(&mut self, id: Index) -> bool {
         let (p0, mask) = (id.offset(SHIFT1), id.mask(SHIFT0));
@@ -133,6 +444,11 @@ impl BitSet {
         if p0 >= self.layer0.len() {
             self.extend(id);
         }
+        debug_assert!(
+            p0 < self.layer0.len(),
+            "Layer 0 is not allocated for index {} after growing, layer lengths are {:?}",
+            id, self.layer_lengths()
+        );
 
         if self.layer0[p0] & mask != 0 {
             return true;
@@ -147,9 +463,21 @@ impl BitSet {
         } else {
             self.layer0[p0] |= mask;
         }
+        self.len += 1;
         false
     }
 
+    /// Adds `id` to the `BitSet`. Returns `true` if the value was newly
+    /// inserted, and `false` if it was already in the set.
+    ///
+    /// This matches `HashSet::insert`, and is the opposite of [`add`].
+    ///
+    /// [`add`]: #method.add
+    #[inline]
+    pub fn insert(&mut self, id: Index) -> bool {
+        !self.add(id)
+    }
+
     fn layer_mut(&mut self, level: usize, idx: usize) -> &mut usize {
         match level {
             0 => {
@@ -189,9 +517,15 @@ impl BitSet {
         // should be cleared if the bit cleared was the last bit
         // in its set
         self.layer0[p0] &= !id.mask(SHIFT0);
+        self.len -= 1;
         if self.layer0[p0] != 0 {
             return true;
         }
+        debug_assert!(
+            p1 < self.layer1.len() && p2 < self.layer2.len(),
+            "Upper layers are not allocated for index {}, layer lengths are {:?}",
+            id, self.layer_lengths()
+        );
 
         self.layer1[p1] &= !id.mask(SHIFT1);
         if self.layer1[p1] != 0 {
@@ -214,317 +548,3479 @@ impl BitSet {
         p0 < self.layer0.len() && (self.layer0[p0] & id.mask(SHIFT0)) != 0
     }
 
-    /// Completely wipes out the bit set.
-    pub fn clear(&mut self) {
-        self.layer0.clear();
-        self.layer1.clear();
-        self.layer2.clear();
-        self.layer3 = 0;
+    /// Returns whether `id` is in the set like [`contains`], but returns an
+    /// error if `id` is too large to ever be in a `BitSet`.
+    ///
+    /// This tells indices which are simply absent apart from indices which
+    /// are most likely a bug of the caller.
+    ///
+    /// [`contains`]: #method.contains
+    pub fn contains_checked(&self, id: Index) -> Result<bool, CapacityError> {
+        if id as usize >= MAX_EID {
+            return Err(CapacityError { index: id });
+        }
+        Ok(self.contains(id))
     }
-}
 
-/// A generic interface for [`BitSetLike`]-like types.
-///
-/// Every `BitSetLike` is hierarchical, meaning that there
-/// are multiple levels that branch out in a tree like structure.
-///
-/// Layer0 each bit represents one Index of the set
-/// Layer1 each bit represents one `usize` of Layer0, and will be
-/// set only if the word below it is not zero.
-/// Layer2 has the same arrangement but with Layer1, and Layer3 with Layer2.
-///
-/// This arrangement allows for rapid jumps across the key-space.
-///
-/// [`BitSetLike`]: ../trait.BitSetLike.html
-pub trait BitSetLike {
-    /// Gets the `usize` corresponding to layer and index.
+    /// Returns `true` if `id` is covered by the allocated words of layer 0,
+    /// so that adding it won't grow the set.
+    #[inline]
+    pub fn contains_capacity(&self, id: Index) -> bool {
+        id.offset(SHIFT1) < self.layer0.len()
+    }
+
+    /// Grows the layers of the set just enough to cover `id`, without
+    /// adding it.
     ///
-    /// The `layer` should be in the range [0, 3]
-    fn get_from_layer(&self, layer: usize, idx: usize) -> usize {
-        match layer {
-            0 => self.layer0(idx),
-            1 => self.layer1(idx),
-            2 => self.layer2(idx),
-            3 => self.layer3(),
-            _ => panic!("Invalid layer: {}", layer),
+    /// Panics if `id` can't be represented.
+    pub fn ensure_index(&mut self, id: Index) {
+        if !self.contains_capacity(id) {
+            self.extend(id);
         }
     }
 
-    /// Return a `usize` where each bit represents if any word in layer2
-    /// has been set.
-    fn layer3(&self) -> usize;
-
-    /// Return the `usize` from the array of usizes that indicates if any
-    /// bit has been set in layer1
-    fn layer2(&self, i: usize) -> usize;
+    /// Grows the layers of the set to cover every index up to `max`, and
+    /// allocates exactly the words needed for it.
+    ///
+    /// This grows the layers like [`Growth::Exact`] does, no matter which
+    /// growth the set is configured with. Layers which already cover `max`
+    /// are left as they are. Panics if `max` can't be represented.
+    ///
+    /// [`Growth::Exact`]: enum.Growth.html#variant.Exact
+    pub fn reserve_exact(&mut self, max: Index) {
+        Self::valid_range(max);
+        let (p0, p1, p2) = offsets(max);
+        Self::grow(&mut self.layer2, p2, Growth::Exact);
+        Self::grow(&mut self.layer1, p1, Growth::Exact);
+        Self::grow(&mut self.layer0, p0, Growth::Exact);
+    }
 
-    /// Return the `usize` from the array of usizes that indicates if any
-    /// bit has been set in layer0
-    fn layer1(&self, i: usize) -> usize;
+    /// Returns the number of allocated words in each layer, starting with
+    /// layer 0. Layer 3 always consists of a single word.
+    ///
+    /// This reports allocation sizes, which aren't affected by removing
+    /// indices from the set, except through [`truncate`].
+    ///
+    /// [`truncate`]: #method.truncate
+    pub fn layer_lengths(&self) -> [usize; LAYERS] {
+        [self.layer0.len(), self.layer1.len(), self.layer2.len(), 1]
+    }
 
-    /// Return a `usize` that maps to the direct 1:1 association with
-    /// each index of the set
-    fn layer0(&self, i: usize) -> usize;
+    /// Creates an iterator over the non-empty blocks of layer 0, where each
+    /// block is the slice of words covered by one word of layer 1.
+    ///
+    /// This is useful to process the set one cache-friendly block of
+    /// `usize_bits * usize_bits` indices at a time.
+    pub fn layer1_blocks(&self) -> Layer1Blocks<'_> {
+        Layer1Blocks::new(self)
+    }
 
-    /// Allows checking if set bit is contained in the bit set.
-    fn contains(&self, i: Index) -> bool;
+    /// Creates an iterator over every word of layer 0 in aligned chunks of
+    /// `chunk` words, including the empty ones.
+    ///
+    /// All chunks but the last have exactly `chunk` words, which suits
+    /// vectorized processing of fixed-width blocks. Panics if `chunk` is
+    /// zero.
+    pub fn aligned_word_chunks(&self, chunk: usize) -> AlignedWordChunks<'_> {
+        AlignedWordChunks::new(self, chunk)
+    }
 
-    /// Create an iterator that will scan over the keyspace
-    fn iter(self) -> BitIter<Self>
-        where Self: Sized
-    {
-        let layer3 = self.layer3();
+    /// Returns the number of indices in the set.
+    ///
+    /// The count is maintained incrementally by every operation that
+    /// modifies the set, so this is `O(1)`.
+    #[inline]
+    pub fn cached_len(&self) -> usize {
+        self.len
+    }
 
-        BitIter::new(self, [0, 0, 0, layer3], [0; LAYERS - 1])
+    /// Creates a new `BitSet` where every index `i < bound` of this set
+    /// is mapped to `bound - 1 - i`.
+    ///
+    /// Indices at or above `bound` have no mirror image and are dropped.
+    pub fn reflect(&self, bound: Index) -> BitSet {
+        let mut reflected = BitSet::new();
+        for i in self.iter().take_while(|&i| i < bound) {
+            reflected.add(bound - 1 - i);
+        }
+        reflected
     }
 
-    /// Create a parallel iterator that will scan over the keyspace
-    #[cfg(feature="parallel")]
-    fn par_iter(self) -> BitParIter<Self>
-        where Self: Sized
-    {
-        BitParIter::new(self)
+    /// Creates a new `BitSet` where every index `i` of this set is mapped
+    /// to `perm[i]`.
+    ///
+    /// Indices at or beyond the end of `perm` have no image and are
+    /// dropped. Panics if an image can't be represented. `perm` doesn't
+    /// have to be a permutation, but if it maps several members to the
+    /// same index they end up as a single member.
+    pub fn apply_permutation(&self, perm: &[Index]) -> BitSet {
+        let mut permuted = BitSet::new();
+        for i in self.iter().take_while(|&i| (i as usize) < perm.len()) {
+            permuted.add(perm[i as usize]);
+        }
+        permuted
     }
-}
 
-/// A extension to the [`BitSetLike`] trait which allows draining it.
-pub trait DrainableBitSet: BitSetLike {
-    /// Removes bit from the bit set.
-    /// 
-    /// Returns `true` if removal happened and `false` otherwise.
-    fn remove(&mut self, i: Index) -> bool;
+    /// Returns the members of the set in ascending order.
+    ///
+    /// The position of a member in the returned vector is its dense index,
+    /// so this can be used to relabel a sparse set of indices into the
+    /// contiguous range `0..len`. See [`dense_index_of`] for the reverse
+    /// lookup.
+    ///
+    /// [`dense_index_of`]: #method.dense_index_of
+    pub fn compact_indices(&self) -> Vec<Index> {
+        let mut indices = Vec::with_capacity(self.len);
+        indices.extend(self.iter());
+        indices
+    }
 
-    /// Create a draining iterator that will scan over the keyspace and clears it while doing so.
-    fn drain<'a>(&'a mut self) -> DrainBitIter<'a, Self>
-        where Self: Sized
-    {
-        let layer3 = self.layer3();
+    /// Returns the dense index of `id`, which is the number of members
+    /// smaller than it, or `None` if `id` is not in the set.
+    ///
+    /// For every member, `compact_indices()[dense_index_of(id)] == id`.
+    pub fn dense_index_of(&self, id: Index) -> Option<usize> {
+        if !self.contains(id) {
+            return None;
+        }
 
-        DrainBitIter::new(self, [0, 0, 0, layer3], [0; LAYERS - 1])
+        let mut rank = 0;
+        if let Some((first, last)) = clamp_range(0..id) {
+            walk_range(self, LAYERS - 1, 0, first, last, &mut |_, word| {
+                rank += word.count_ones() as usize;
+                true
+            });
+        }
+        Some(rank)
     }
-}
 
-impl<'a, T> BitSetLike for &'a T
-    where T: BitSetLike
-{
-    #[inline]
-    fn layer3(&self) -> usize {
-        (*self).layer3()
+    /// Removes every index of `other` from this set.
+    ///
+    /// `other` can be any `BitSetLike`, including composite expressions.
+    /// Its members are removed word by word, and the upper layers of this
+    /// set are only updated for words that become empty.
+    pub fn remove_all<B: BitSetLike>(&mut self, other: &B) {
+        let mut iter = other.iter();
+        while let Some((idx, word)) = iter.next_word() {
+            if idx >= self.layer0.len() {
+                break;
+            }
+            self.clear_word_bits(idx, word);
+        }
     }
 
-    #[inline]
-    fn layer2(&self, i: usize) -> usize {
-        (*self).layer2(i)
-    }
+    /// Splits the set into the indices for which `f` returns `true`, and
+    /// those for which it returns `false`.
+    ///
+    /// The members are visited in ascending order. Each word of layer 0 is
+    /// split into the two results as a whole, and their upper layers are
+    /// built once at the end.
+    pub fn partition<F: FnMut(Index) -> bool>(&self, mut f: F) -> (BitSet, BitSet) {
+        let words = vec![0; self.layer0.len()];
+        let mut matching = BitSet { layer0: words.clone(), ..BitSet::new() };
+        let mut rest = BitSet { layer0: words, ..BitSet::new() };
 
-    #[inline]
-    fn layer1(&self, i: usize) -> usize {
-        (*self).layer1(i)
+        let mut iter = self.iter();
+        while let Some((idx, word)) = iter.next_word() {
+            let mut bits = word;
+            let mut matched = 0;
+            while bits != 0 {
+                let bit = bits.trailing_zeros() as usize;
+                bits &= !(1 << bit);
+                if f(((idx << BITS) | bit) as Index) {
+                    matched |= 1 << bit;
+                }
+            }
+            matching.layer0[idx] = matched;
+            rest.layer0[idx] = word & !matched;
+        }
+        matching.rebuild_upper_layers();
+        rest.rebuild_upper_layers();
+        (matching, rest)
     }
 
-    #[inline]
-    fn layer0(&self, i: usize) -> usize {
-        (*self).layer0(i)
+    /// Replaces every allocated word of layer 0 with the result of calling
+    /// `f` with its index and value, and rebuilds the upper layers once
+    /// afterwards.
+    ///
+    /// This allows custom bitwise operations on the set, such as masking
+    /// or shifting within words. The words are visited in ascending order,
+    /// including the empty ones, but the set isn't grown.
+    pub fn transform_words<F: FnMut(usize, usize) -> usize>(&mut self, mut f: F) {
+        for (idx, word) in self.layer0.iter_mut().enumerate() {
+            *word = f(idx, *word);
+        }
+        self.rebuild_upper_layers();
     }
 
-    #[inline]
-    fn contains(&self, i: Index) -> bool {
-        (*self).contains(i)
+    /// Adds every index `i` of `other` to this set as `i + shift`.
+    ///
+    /// The words of `other` are shifted as a whole, and indices that are
+    /// shifted below zero or beyond the maximum index are dropped.
+    pub fn union_shifted(&mut self, other: &BitSet, shift: i64) {
+        let (offset, bit) = (shift.div_euclid(1 << BITS), shift.rem_euclid(1 << BITS) as u32);
+        let mut iter = other.iter();
+        while let Some((idx, word)) = iter.next_word() {
+            let lo = idx as i64 + offset;
+            let hi = if bit == 0 { 0 } else { word >> ((1 << BITS) - bit) };
+            for &(idx, mask) in &[(lo, word << bit), (lo + 1, hi)] {
+                if mask != 0 && (0..(MAX_EID >> BITS) as i64).contains(&idx) {
+                    self.extend((idx << BITS) as Index);
+                    self.set_word_bits(idx as usize, mask);
+                }
+            }
+        }
     }
-}
 
-impl<'a, T> BitSetLike for &'a mut T
-    where T: BitSetLike
-{
-    #[inline]
-    fn layer3(&self) -> usize {
-        (**self).layer3()
+    /// Creates a new `BitSet` where every index of this set is spread out
+    /// to all indices at most `radius` away from it.
+    ///
+    /// This is the union of every shift of the set in `-radius..=radius`,
+    /// but the covered radius is doubled with every [`union_shifted`], so
+    /// only a logarithmic number of shifts are needed.
+    ///
+    /// [`union_shifted`]: #method.union_shifted
+    pub fn dilate(&self, radius: Index) -> BitSet {
+        let mut dilated = self.clone();
+        let mut covered = 0;
+        while covered < radius {
+            let step = ::std::cmp::min(covered + 1, radius - covered);
+            let previous = dilated.clone();
+            dilated.union_shifted(&previous, step as i64);
+            dilated.union_shifted(&previous, -(step as i64));
+            covered += step;
+        }
+        dilated
     }
 
-    #[inline]
-    fn layer2(&self, i: usize) -> usize {
-        (**self).layer2(i)
+    /// Creates a new `BitSet` holding only the indices of this set whose
+    /// every index at most `radius` away is in the set as well.
+    ///
+    /// This is the intersection of every shift of the set in
+    /// `-radius..=radius`, and like [`dilate`] the covered radius is
+    /// doubled with every step. Indices closer than `radius` to zero or to
+    /// the maximum index lack part of their neighbourhood, and are removed.
+    ///
+    /// [`dilate`]: #method.dilate
+    pub fn erode(&self, radius: Index) -> BitSet {
+        let mut eroded = self.clone();
+        let mut covered = 0;
+        while covered < radius && !eroded.is_empty() {
+            let step = ::std::cmp::min(covered + 1, radius - covered);
+            let (mut up, mut down) = (BitSet::new(), BitSet::new());
+            up.union_shifted(&eroded, step as i64);
+            down.union_shifted(&eroded, -(step as i64));
+            eroded.retain_intersection(&BitSetAnd(&up, &down));
+            covered += step;
+        }
+        eroded
     }
 
-    #[inline]
-    fn layer1(&self, i: usize) -> usize {
-        (**self).layer1(i)
+    /// Adds every index of `other` to this set, given that the two sets
+    /// have no index in common.
+    ///
+    /// Since no word of layer 0 can then have bits from both sets, the
+    /// layers of `other` are merged into this set directly, and the cached
+    /// length is just added up. Overlapping sets are detected by a debug
+    /// assertion, and in release builds leave the cached length too high.
+    pub fn merge_disjoint(&mut self, other: BitSet) {
+        if other.layer0.is_empty() {
+            return;
+        }
+        Self::fill_up(&mut self.layer2, other.layer2.len() - 1);
+        Self::fill_up(&mut self.layer1, other.layer1.len() - 1);
+        Self::fill_up(&mut self.layer0, other.layer0.len() - 1);
+
+        for (word, &theirs) in self.layer0.iter_mut().zip(&other.layer0) {
+            debug_assert!(*word & theirs == 0, "Expected sets to be disjoint");
+            *word |= theirs;
+        }
+        for (word, &theirs) in self.layer1.iter_mut().zip(&other.layer1) {
+            *word |= theirs;
+        }
+        for (word, &theirs) in self.layer2.iter_mut().zip(&other.layer2) {
+            *word |= theirs;
+        }
+        self.layer3 |= other.layer3;
+        self.len += other.len;
     }
 
-    #[inline]
-    fn layer0(&self, i: usize) -> usize {
-        (**self).layer0(i)
+    /// Removes every index from this set which isn't in `other`.
+    ///
+    /// `other` can be any `BitSetLike`, including composite expressions.
+    /// Each non-empty word of this set is masked with the matching word of
+    /// `other`, and the upper layers are rebuilt once afterwards.
+    pub fn retain_intersection<B: BitSetLike>(&mut self, other: &B) {
+        for (idx, word) in self.layer0.iter_mut().enumerate() {
+            if *word != 0 {
+                *word &= other.layer0(idx);
+            }
+        }
+        self.rebuild_upper_layers();
     }
 
+    /// Adds every index in `range` to the set, a word at a time.
+    fn add_range(&mut self, range: Range<Index>) {
+        if range.start >= range.end {
+            return;
+        }
+        let (first, last) = (range.start, range.end - 1);
+        self.extend(last);
+
+        let (w0, w1) = (first.offset(SHIFT1), last.offset(SHIFT1));
+        for w in w0..(w1 + 1) {
+            let mut mask = !0;
+            if w == w0 {
+                mask &= !0 << first.row(SHIFT0);
+            }
+            if w == w1 {
+                mask &= !0 >> ((1 << BITS) - 1 - last.row(SHIFT0));
+            }
+            self.set_word_bits(w, mask);
+        }
+    }
+
+    /// Removes every index in `range` from the set, and returns the number
+    /// of indices that were removed.
+    ///
+    /// The count falls out of the cached length, so this costs no more
+    /// than clearing the range.
+    pub fn remove_range_count(&mut self, range: Range<Index>) -> usize {
+        let len = self.len;
+        self.remove_range(range);
+        len - self.len
+    }
+
+    /// Removes every index in `range` from the set, a word at a time.
+    fn remove_range(&mut self, range: Range<Index>) {
+        let (first, last) = match clamp_range(range) {
+            Some((first, last)) if first >> BITS < self.layer0.len() => (first, last),
+            _ => return,
+        };
+        let (w0, w1) = (first >> BITS, ::std::cmp::min(last >> BITS, self.layer0.len() - 1));
+        for w in w0..(w1 + 1) {
+            let mut mask = !0;
+            if w == first >> BITS {
+                mask &= !0 << (first & ((1 << BITS) - 1));
+            }
+            if w == last >> BITS {
+                mask &= !0 >> ((1 << BITS) - 1 - (last & ((1 << BITS) - 1)));
+            }
+            self.clear_word_bits(w, mask);
+        }
+    }
+
+    /// Keeps only the `k` largest indices of the set, removing the rest.
+    ///
+    /// The words of layer 0 are counted from the top down until `k`
+    /// indices are found, and everything below the cutoff is removed.
+    pub fn retain_top_k(&mut self, k: usize) {
+        if k >= self.len {
+            return;
+        }
+        let mut remaining = k;
+        let mut cutoff = 0;
+        for idx in (0..self.layer0.len()).rev() {
+            let mut word = self.layer0[idx];
+            let ones = word.count_ones() as usize;
+            if ones < remaining {
+                remaining -= ones;
+                continue;
+            }
+            // drop the `remaining` highest bits, the next one is the
+            // largest index to remove
+            for _ in 0..remaining {
+                word &= !(1 << ((1 << BITS) - 1 - word.leading_zeros() as usize));
+            }
+            cutoff = (idx << BITS) + (1 << BITS) - word.leading_zeros() as usize;
+            break;
+        }
+        self.remove_range(0..cutoff as Index);
+    }
+
+    /// Keeps only the `k` smallest indices of the set, removing the rest.
+    ///
+    /// The words of layer 0 are counted from the bottom up until the index
+    /// following the first `k` is found, and everything from it onwards is
+    /// removed.
+    pub fn retain_bottom_k(&mut self, k: usize) {
+        if k >= self.len {
+            return;
+        }
+        let mut remaining = k;
+        let mut cutoff = 0;
+        let mut iter = self.iter();
+        while let Some((idx, mut word)) = iter.next_word() {
+            let ones = word.count_ones() as usize;
+            if ones <= remaining {
+                remaining -= ones;
+                continue;
+            }
+            for _ in 0..remaining {
+                word &= word - 1;
+            }
+            cutoff = (idx << BITS) | word.trailing_zeros() as usize;
+            break;
+        }
+        self.remove_range(cutoff as Index..MAX_EID as Index);
+    }
+
+    /// Removes every index that is `max` or above, and shrinks the layers
+    /// to the words still needed to cover the indices below `max`.
+    pub fn truncate(&mut self, max: Index) {
+        self.remove_range(max..MAX_EID as Index);
+        let (p0, p1, p2) = match max.checked_sub(1) {
+            Some(last) => offsets(::std::cmp::min(last, MAX_EID as Index - 1)),
+            None => {
+                *self = BitSet { growth: self.growth, ..BitSet::new() };
+                return;
+            }
+        };
+        for (layer, p) in [(&mut self.layer0, p0), (&mut self.layer1, p1), (&mut self.layer2, p2)] {
+            layer.truncate(p + 1);
+            layer.shrink_to_fit();
+        }
+    }
+
+    /// Flips the membership of every index in `range`.
+    ///
+    /// Whole words of layer 0 are flipped at once, and the upper layers
+    /// are recomputed for the affected words afterwards, since toggling
+    /// can both empty and fill them.
+    pub fn toggle_range(&mut self, range: Range<Index>) {
+        if range.start >= range.end {
+            return;
+        }
+        let (first, last) = (range.start, range.end - 1);
+        self.extend(last);
+
+        let (w0, w1) = (first.offset(SHIFT1), last.offset(SHIFT1));
+        for w in w0..(w1 + 1) {
+            let mut mask = !0;
+            if w == w0 {
+                mask &= !0 << first.row(SHIFT0);
+            }
+            if w == w1 {
+                mask &= !0 >> ((1 << BITS) - 1 - last.row(SHIFT0));
+            }
+            let old = self.layer0[w];
+            self.layer0[w] ^= mask;
+            self.len = self.len - old.count_ones() as usize + self.layer0[w].count_ones() as usize;
+        }
+        self.refresh_upper_layers(w0, w1);
+    }
+
+    /// Flips the membership of every index below `bound` in place, and
+    /// removes every index at or above `bound`.
+    ///
+    /// This is the set `BitSetNotBounded` describes, without collecting it
+    /// into a new set. The words below `bound` are flipped a word at a time
+    /// like [`toggle_range`].
+    ///
+    /// [`toggle_range`]: #method.toggle_range
+    pub fn invert(&mut self, bound: Index) {
+        self.remove_range(bound..MAX_EID as Index);
+        self.toggle_range(0..bound);
+    }
+
+    /// Creates a new `BitSet` holding the members of this set which are in
+    /// `range`.
+    ///
+    /// The overlapping words of layer 0 are copied with the bits outside
+    /// of the range masked off, and the upper layers are built once from
+    /// them afterwards.
+    pub fn clone_range(&self, range: Range<Index>) -> BitSet {
+        let mut clone = BitSet::new();
+        if let Some((first, last)) = clamp_range(range) {
+            walk_range(self, LAYERS - 1, 0, first, last, &mut |idx, word| {
+                Self::fill_up(&mut clone.layer0, idx);
+                clone.layer0[idx] = word;
+                true
+            });
+        }
+        clone.rebuild_upper_layers();
+        clone
+    }
+
+    /// Swaps the contents of this set with `other`, together with their
+    /// allocations.
+    ///
+    /// This is the same as `std::mem::swap`. See [`swap_keep_capacity`] to
+    /// let each set keep its own allocation.
+    ///
+    /// [`swap_keep_capacity`]: #method.swap_keep_capacity
     #[inline]
-    fn contains(&self, i: Index) -> bool {
-        (**self).contains(i)
+    pub fn swap_contents(&mut self, other: &mut BitSet) {
+        ::std::mem::swap(self, other);
+    }
+
+    /// Swaps the members of this set with `other` word by word, so that
+    /// each set keeps its own allocation.
+    ///
+    /// This is useful for double-buffering, where both sets are expected
+    /// to stay preallocated. Each set keeps its capacity as long as the
+    /// members of the other one fit within its allocated words, otherwise
+    /// it grows just enough to hold them.
+    pub fn swap_keep_capacity(&mut self, other: &mut BitSet) {
+        Self::swap_words(&mut self.layer0, &mut other.layer0);
+        Self::swap_words(&mut self.layer1, &mut other.layer1);
+        Self::swap_words(&mut self.layer2, &mut other.layer2);
+        ::std::mem::swap(&mut self.layer3, &mut other.layer3);
+        ::std::mem::swap(&mut self.len, &mut other.len);
+    }
+
+    fn swap_words(a: &mut Vec<usize>, b: &mut Vec<usize>) {
+        let common = ::std::cmp::min(a.len(), b.len());
+        a[..common].swap_with_slice(&mut b[..common]);
+        let (short, long) = if a.len() < b.len() { (a, b) } else { (b, a) };
+        // the words past the end of the shorter vector move over in full.
+        if let Some(last) = long[common..].iter().rposition(|&word| word != 0) {
+            short.extend_from_slice(&long[common..common + last + 1]);
+        }
+        for word in &mut long[common..] {
+            *word = 0;
+        }
+    }
+
+    /// Edits the words of layer 0 directly through a [`Layer0Editor`], and
+    /// rebuilds the upper layers once `f` returns.
+    ///
+    /// This is cheaper than a long sequence of `add` and `remove` calls,
+    /// which each keep the upper layers up to date.
+    ///
+    /// [`Layer0Editor`]: struct.Layer0Editor.html
+    pub fn edit<F: FnOnce(&mut Layer0Editor)>(&mut self, f: F) {
+        f(&mut Layer0Editor { layer0: &mut self.layer0 });
+        self.rebuild_upper_layers();
+    }
+
+    /// Splits the allocated words of layer 0 into non-overlapping
+    /// [`Layer0Chunk`]s, which `f` can edit independently, for example
+    /// from different threads, and rebuilds the upper layers once `f`
+    /// returns.
+    ///
+    /// The chunks cover the words up to the first boundary, from each
+    /// boundary to the next, and from the last boundary to the end of the
+    /// allocation, so there is one more chunk than there are boundaries.
+    /// Panics unless the boundaries are ascending and within the allocated
+    /// words, see [`ensure_index`] to grow the set beforehand.
+    ///
+    /// [`Layer0Chunk`]: struct.Layer0Chunk.html
+    /// [`ensure_index`]: #method.ensure_index
+    pub fn split_words_mut<F: FnOnce(Vec<Layer0Chunk>)>(&mut self, boundaries: &[usize], f: F) {
+        let len = self.layer0.len();
+        let mut chunks = Vec::with_capacity(boundaries.len() + 1);
+        let (mut rest, mut start) = (&mut self.layer0[..], 0);
+        for &boundary in boundaries {
+            if boundary < start || boundary > len {
+                panic!("Expected ascending boundaries of at most {} words, found {}", len, boundary);
+            }
+            let (words, tail) = ::std::mem::take(&mut rest).split_at_mut(boundary - start);
+            chunks.push(Layer0Chunk { start, words });
+            rest = tail;
+            start = boundary;
+        }
+        chunks.push(Layer0Chunk { start, words: rest });
+        f(chunks);
+        self.rebuild_upper_layers();
+    }
+
+    /// Returns a virtual set of the indices in both this set and `other`.
+    ///
+    /// This is the same as `BitSetAnd(&self, other)`, or `&self & other`.
+    pub fn and<'a, B: BitSetLike>(&'a self, other: &'a B) -> BitSetAnd<&'a Self, &'a B> {
+        BitSetAnd(self, other)
+    }
+
+    /// Returns a virtual set of the indices in this set, `other` or both.
+    ///
+    /// This is the same as `BitSetOr(&self, other)`, or `&self | other`.
+    pub fn or<'a, B: BitSetLike>(&'a self, other: &'a B) -> BitSetOr<&'a Self, &'a B> {
+        BitSetOr(self, other)
+    }
+
+    /// Returns a virtual set of every index which isn't in this set.
+    ///
+    /// This is the same as `BitSetNot(&self)`, or `!&self`.
+    pub fn not(&self) -> BitSetNot<&Self> {
+        BitSetNot(self)
+    }
+
+    /// Create an iterator over the indices which are in this set but not in
+    /// `baseline`, such as the indices added since an earlier copy of the
+    /// set was taken.
+    ///
+    /// See [`iter_removed_since`] for the opposite direction.
+    ///
+    /// [`iter_removed_since`]: #method.iter_removed_since
+    pub fn iter_added_since<'a>(&'a self, baseline: &'a BitSet) -> BitIter<BitSetAnd<&'a BitSet, BitSetNot<&'a BitSet>>> {
+        self.iter_difference(baseline)
+    }
+
+    /// Create an iterator over the indices which are in `baseline` but not
+    /// in this set, such as the indices removed since an earlier copy of
+    /// the set was taken.
+    pub fn iter_removed_since<'a>(&'a self, baseline: &'a BitSet) -> BitIter<BitSetAnd<&'a BitSet, BitSetNot<&'a BitSet>>> {
+        baseline.iter_difference(self)
+    }
+
+    /// Compares two sets lexicographically by their members in ascending
+    /// order.
+    ///
+    /// This is a total order which can be used to sort sets, for example
+    /// with `sort_by`. Sets with the same members compare as equal no matter
+    /// how much memory they have allocated. Note that this order has nothing
+    /// to do with sets being subsets of each other.
+    pub fn canonical_cmp(&self, other: &BitSet) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+
+    /// Returns `true` if the set holds exactly the indices of the hash set.
+    ///
+    /// Indices of the hash set which a `BitSet` can't represent are never
+    /// members, so the sets aren't equal if there are any of those.
+    pub fn equals_hashset(&self, other: &HashSet<Index>) -> bool {
+        self.len == other.len() && self.iter().all(|i| other.contains(&i))
+    }
+
+    /// Returns `true` if the set holds exactly one index.
+    #[inline]
+    pub fn is_single(&self) -> bool {
+        self.len == 1
+    }
+
+    /// Returns the only index of the set, or `None` unless the set holds
+    /// exactly one index.
+    pub fn single_element(&self) -> Option<Index> {
+        if !self.is_single() {
+            return None;
+        }
+        let p2 = self.layer3.trailing_zeros() as usize;
+        let p1 = (p2 << BITS) | self.layer2[p2].trailing_zeros() as usize;
+        let p0 = (p1 << BITS) | self.layer1[p1].trailing_zeros() as usize;
+        Some(((p0 << BITS) | self.layer0[p0].trailing_zeros() as usize) as Index)
+    }
+
+    /// Returns the fraction of the indices up to and including the largest
+    /// member which are in the set, or `0.0` if the set is empty.
+    ///
+    /// A set holding every index from zero up reports `1.0`. The largest
+    /// member is found by following the highest bits down the layers, so
+    /// this is cheap no matter how large the set is.
+    pub fn density(&self) -> f64 {
+        let p0 = match self.max_word_index() {
+            Some(p0) => p0,
+            None => return 0.0,
+        };
+        let max = (p0 << BITS) | ((1 << BITS) - 1 - self.layer0[p0].leading_zeros() as usize);
+        self.len as f64 / (max + 1) as f64
+    }
+
+    /// Moves the contents out of this set, leaving it empty.
+    ///
+    /// The returned set takes over the allocation, and this set starts over
+    /// without one. See [`take_keep_capacity`] to keep it instead.
+    ///
+    /// [`take_keep_capacity`]: #method.take_keep_capacity
+    pub fn take(&mut self) -> BitSet {
+        let empty = BitSet {
+            growth: self.growth,
+            ..BitSet::new()
+        };
+        ::std::mem::replace(self, empty)
+    }
+
+    /// Copies the contents out of this set into a new allocation, and
+    /// clears this set while keeping its own allocation for reuse.
+    pub fn take_keep_capacity(&mut self) -> BitSet {
+        let taken = self.clone();
+        self.clear();
+        taken
+    }
+
+    /// Removes every index from the set, and makes sure the layers cover
+    /// every index up to `max`.
+    ///
+    /// The words are zeroed in place, so the layers are never shrunk, and
+    /// adding indices up to `max` afterwards won't reallocate. Panics if
+    /// `max` can't be represented.
+    pub fn reset_to_capacity(&mut self, max: Index) {
+        for word in self.layer0.iter_mut().chain(self.layer1.iter_mut()).chain(self.layer2.iter_mut()) {
+            *word = 0;
+        }
+        self.layer3 = 0;
+        self.len = 0;
+        self.ensure_index(max);
+    }
+
+    /// Completely wipes out the bit set.
+    pub fn clear(&mut self) {
+        self.layer0.clear();
+        self.layer1.clear();
+        self.layer2.clear();
+        self.layer3 = 0;
+        self.len = 0;
+    }
+}
+
+/// Word-level access to layer 0 of a `BitSet`, handed out by
+/// [`BitSet::edit`].
+///
+/// Bit `b` of word `w` represents the index `w * usize_bits + b`. Writing
+/// a word beyond the end of the set grows it, and panics if the word
+/// can't be represented.
+///
+/// [`BitSet::edit`]: struct.BitSet.html#method.edit
+#[derive(Debug)]
+pub struct Layer0Editor<'a> {
+    layer0: &'a mut Vec<usize>,
+}
+
+impl<'a> Layer0Editor<'a> {
+    fn word_mut(&mut self, word_index: usize) -> &mut usize {
+        if word_index >= MAX_EID >> BITS {
+            panic!("Expected word index to be less then {}, found {}", MAX_EID >> BITS, word_index);
+        }
+        BitSet::fill_up(self.layer0, word_index);
+        &mut self.layer0[word_index]
+    }
+
+    /// Replaces word `word_index` with `value`.
+    pub fn set_word(&mut self, word_index: usize, value: usize) {
+        *self.word_mut(word_index) = value;
+    }
+
+    /// Clears every bit of word `word_index`.
+    pub fn clear_word(&mut self, word_index: usize) {
+        if let Some(word) = self.layer0.get_mut(word_index) {
+            *word = 0;
+        }
+    }
+
+    /// Merges `value` into word `word_index`.
+    pub fn or_word(&mut self, word_index: usize, value: usize) {
+        *self.word_mut(word_index) |= value;
+    }
+}
+
+/// A mutable range of words of layer 0 of a `BitSet`, handed out by
+/// [`BitSet::split_words_mut`].
+///
+/// Words are addressed by their index in the whole set, like with a
+/// [`Layer0Editor`], but only the words within [`word_range`] can be
+/// written, and writing any other word panics.
+///
+/// [`BitSet::split_words_mut`]: struct.BitSet.html#method.split_words_mut
+/// [`Layer0Editor`]: struct.Layer0Editor.html
+/// [`word_range`]: #method.word_range
+#[derive(Debug)]
+pub struct Layer0Chunk<'a> {
+    start: usize,
+    words: &'a mut [usize],
+}
+
+impl<'a> Layer0Chunk<'a> {
+    /// Returns the indices of the words covered by this chunk.
+    pub fn word_range(&self) -> Range<usize> {
+        self.start..self.start + self.words.len()
+    }
+
+    /// Returns the words of this chunk, the first one being word
+    /// `word_range().start` of the set.
+    pub fn words_mut(&mut self) -> &mut [usize] {
+        self.words
+    }
+
+    fn word_mut(&mut self, word_index: usize) -> &mut usize {
+        let range = self.word_range();
+        if !range.contains(&word_index) {
+            panic!("Expected word index within {:?}, found {}", range, word_index);
+        }
+        &mut self.words[word_index - self.start]
+    }
+
+    /// Replaces word `word_index` with `value`.
+    pub fn set_word(&mut self, word_index: usize, value: usize) {
+        *self.word_mut(word_index) = value;
+    }
+
+    /// Clears every bit of word `word_index`.
+    pub fn clear_word(&mut self, word_index: usize) {
+        *self.word_mut(word_index) = 0;
+    }
+
+    /// Merges `value` into word `word_index`.
+    pub fn or_word(&mut self, word_index: usize, value: usize) {
+        *self.word_mut(word_index) |= value;
+    }
+}
+
+/// A generic interface for [`BitSetLike`]-like types.
+///
+/// Every `BitSetLike` is hierarchical, meaning that there
+/// are multiple levels that branch out in a tree like structure.
+///
+/// Layer0 each bit represents one Index of the set
+/// Layer1 each bit represents one `usize` of Layer0, and will be
+/// set only if the word below it is not zero.
+/// Layer2 has the same arrangement but with Layer1, and Layer3 with Layer2.
+///
+/// This arrangement allows for rapid jumps across the key-space.
+///
+/// [`BitSetLike`]: ../trait.BitSetLike.html
+pub trait BitSetLike {
+    /// Gets the `usize` corresponding to layer and index.
+    ///
+    /// The `layer` should be in the range [0, 3]
+    fn get_from_layer(&self, layer: usize, idx: usize) -> usize {
+        match layer {
+            0 => self.layer0(idx),
+            1 => self.layer1(idx),
+            2 => self.layer2(idx),
+            3 => self.layer3(),
+            _ => panic!("Invalid layer: {}", layer),
+        }
+    }
+
+    /// Return a `usize` where each bit represents if any word in layer2
+    /// has been set.
+    fn layer3(&self) -> usize;
+
+    /// Return the `usize` from the array of usizes that indicates if any
+    /// bit has been set in layer1
+    fn layer2(&self, i: usize) -> usize;
+
+    /// Return the `usize` from the array of usizes that indicates if any
+    /// bit has been set in layer0
+    fn layer1(&self, i: usize) -> usize;
+
+    /// Return a `usize` that maps to the direct 1:1 association with
+    /// each index of the set
+    fn layer0(&self, i: usize) -> usize;
+
+    /// Allows checking if set bit is contained in the bit set.
+    fn contains(&self, i: Index) -> bool;
+
+    /// Returns the word of layer 0 at `i`, or `None` if it's beyond the
+    /// storage backing the set.
+    ///
+    /// This tells apart words which are empty from words which aren't
+    /// stored at all. The default implementation has no notion of storage
+    /// and always returns `Some`.
+    fn layer0_checked(&self, i: usize) -> Option<usize> {
+        Some(self.layer0(i))
+    }
+
+    /// Returns the number of indices in the set.
+    ///
+    /// The default implementation adds up the set bits of every non-empty
+    /// word in layer 0.
+    fn count(&self) -> usize {
+        let mut iter = BitIter::new(self, [0, 0, 0, self.layer3()], [0; LAYERS - 1]);
+        let mut count = 0;
+        while let Some((_, word)) = iter.next_word() {
+            count += word.count_ones() as usize;
+        }
+        count
+    }
+
+    /// Estimates the number of indices in the set without reading all of
+    /// layer 0.
+    ///
+    /// The number of non-empty words of layer 0 is counted exactly from
+    /// layer 1, and multiplied with the average fill of up to 64 words
+    /// sampled evenly across the set. Since every non-empty word holds
+    /// between 1 and `usize_bits` indices, the estimate is never off by
+    /// more than that factor, and it is exact for sets where all words are
+    /// equally full. Sets whose upper layers may claim words that are empty
+    /// in layer 0, such as intersections, are overestimated.
+    fn approx_count(&self) -> usize {
+        const SAMPLES: usize = 64;
+
+        // Calls `f` with the index and word of every non-empty word of
+        // layer 1, in ascending order.
+        fn blocks<T: BitSetLike + ?Sized, F: FnMut(usize, usize)>(set: &T, mut f: F) {
+            let mut l3 = set.layer3();
+            while l3 != 0 {
+                let p2 = l3.trailing_zeros() as usize;
+                l3 &= !(1 << p2);
+                let mut l2 = set.layer2(p2);
+                while l2 != 0 {
+                    let bit = l2.trailing_zeros() as usize;
+                    l2 &= !(1 << bit);
+                    let p1 = (p2 << BITS) | bit;
+                    let l1 = set.layer1(p1);
+                    if l1 != 0 {
+                        f(p1, l1);
+                    }
+                }
+            }
+        }
+
+        let (mut count, mut words) = (0, 0);
+        blocks(self, |_, l1| {
+            count += 1;
+            words += l1.count_ones() as usize;
+        });
+        if count == 0 {
+            return 0;
+        }
+
+        // a second pass samples the blocks, so they don't have to be kept
+        let stride = ::std::cmp::max(count / SAMPLES, 1);
+        let (mut seen, mut bits, mut sampled) = (0, 0, 0);
+        blocks(self, |p1, l1| {
+            if seen % stride == 0 {
+                let p0 = (p1 << BITS) | l1.trailing_zeros() as usize;
+                bits += self.layer0(p0).count_ones() as usize;
+                sampled += 1;
+            }
+            seen += 1;
+        });
+        words * bits / sampled
+    }
+
+    /// Returns `true` if the set holds at least `k` indices.
+    ///
+    /// The default implementation adds up the set bits of the words in
+    /// layer 0 like [`count`], but stops as soon as it reaches `k`.
+    ///
+    /// [`count`]: #method.count
+    fn count_at_least(&self, k: usize) -> bool {
+        let mut iter = BitIter::new(self, [0, 0, 0, self.layer3()], [0; LAYERS - 1]);
+        let mut count = 0;
+        while count < k {
+            match iter.next_word() {
+                Some((_, word)) => count += word.count_ones() as usize,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Returns `true` if the set doesn't contain any index.
+    ///
+    /// The default implementation descends the hierarchy until it finds a
+    /// non-empty word in layer 0, since the upper layers of composite sets
+    /// can have bits set even though nothing below them is.
+    fn is_empty(&self) -> bool {
+        is_empty_by_descent(self)
+    }
+
+    /// Returns the index of the first non-empty word of layer 0, which is
+    /// the word holding the smallest index of the set.
+    ///
+    /// Returns `None` if the set is empty.
+    fn first_set_word(&self) -> Option<usize> {
+        BitIter::new(self, [0, 0, 0, self.layer3()], [0; LAYERS - 1])
+            .next_word()
+            .map(|(idx, _)| idx)
+    }
+
+    /// Returns the index of the last non-empty word of layer 0, which is
+    /// the word holding the largest index of the set.
+    ///
+    /// Returns `None` if the set is empty. The default implementation
+    /// descends from the highest bits of the upper layers, and backtracks
+    /// where they claim words that turn out to be empty.
+    fn max_word_index(&self) -> Option<usize> {
+        let high = |word: usize| (1 << BITS) - 1 - word.leading_zeros() as usize;
+        let mut l3 = self.layer3();
+        while l3 != 0 {
+            let p2 = high(l3);
+            l3 &= !(1 << p2);
+            let mut l2 = self.layer2(p2);
+            while l2 != 0 {
+                let bit = high(l2);
+                l2 &= !(1 << bit);
+                let p1 = (p2 << BITS) | bit;
+                let mut l1 = self.layer1(p1);
+                while l1 != 0 {
+                    let bit = high(l1);
+                    l1 &= !(1 << bit);
+                    let p0 = (p1 << BITS) | bit;
+                    if self.layer0(p0) != 0 {
+                        return Some(p0);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns `true` if any index within `range` is in the set.
+    ///
+    /// This stops at the first hit, and uses the upper layers to skip
+    /// over empty regions of the range.
+    fn any_in_range(&self, range: Range<Index>) -> bool {
+        match clamp_range(range) {
+            Some((first, last)) => !walk_range(self, LAYERS - 1, 0, first, last, &mut |_, _| false),
+            None => false,
+        }
+    }
+
+    /// Returns `true` if any of `ids` is in the set.
+    ///
+    /// Consecutive ids in the same word of layer 0 are tested against a
+    /// single load of that word, so slices with locality are cheap to
+    /// check.
+    fn contains_any_of(&self, ids: &[Index]) -> bool {
+        memberships(self, ids).any(|member| member)
+    }
+
+    /// Returns `true` if all of `ids` are in the set.
+    ///
+    /// Like [`contains_any_of`], each word of layer 0 is only loaded once
+    /// for consecutive ids in it.
+    ///
+    /// [`contains_any_of`]: #method.contains_any_of
+    fn contains_all_of(&self, ids: &[Index]) -> bool {
+        memberships(self, ids).all(|member| member)
+    }
+
+    /// Divides the keyspace into `buckets` regions of equal size, and
+    /// returns the number of indices of the set in each of them.
+    ///
+    /// Each region is counted separately with the upper layers skipping
+    /// over its empty parts, so sparse sets are cheap to profile. Panics if
+    /// `buckets` is zero.
+    fn density_profile(&self, buckets: usize) -> Vec<usize> {
+        assert!(buckets > 0, "Expected a bucket count above zero");
+        // computed in `u64`, since `i * MAX_EID` overflows `Index`
+        let boundary = |i: usize| (i as u64 * MAX_EID as u64 / buckets as u64) as Index;
+        (0..buckets)
+            .map(|i| count_in_range(self, boundary(i)..boundary(i + 1)))
+            .collect()
+    }
+
+    /// Slides a window of `size` indices over the set, advancing by `step`
+    /// indices at a time, and yields the start of every window together
+    /// with the number of indices of the set within it.
+    ///
+    /// The first window starts at zero, and windows are yielded for as long
+    /// as the set has indices at or after their start. Panics if `step` is
+    /// zero.
+    fn windows(&self, size: Index, step: Index) -> Windows<&Self>
+        where Self: Sized
+    {
+        Windows::new(self, size, step)
+    }
+
+    /// Returns the first index of the set, in ascending order, for which
+    /// `f` returns `true`.
+    ///
+    /// Iteration stops at the first match. Trait objects can be searched
+    /// through a reference or a `Box`, which are `BitSetLike` themselves.
+    fn find<F>(&self, mut f: F) -> Option<Index>
+        where Self: Sized,
+              F: FnMut(Index) -> bool
+    {
+        self.iter().find(|&i| f(i))
+    }
+
+    /// Iterates over the indices of the set in ascending order for as long
+    /// as `f` returns `true`, stopping at the first index for which it
+    /// returns `false`.
+    ///
+    /// This is `take_while` over [`iter`], but works through a reference so
+    /// the set doesn't have to be given up.
+    ///
+    /// [`iter`]: #method.iter
+    fn iter_while<F>(&self, f: F) -> TakeWhileBitIter<&Self, F>
+        where Self: Sized,
+              F: FnMut(Index) -> bool
+    {
+        TakeWhileBitIter::new(self, f)
+    }
+
+    /// Calls `f` with every index of the set in ascending order, stopping
+    /// at and returning the first error.
+    ///
+    /// Like [`find`], trait objects can use this through a reference or a
+    /// `Box`.
+    ///
+    /// [`find`]: #method.find
+    fn try_for_each<E, F>(&self, mut f: F) -> Result<(), E>
+        where Self: Sized,
+              F: FnMut(Index) -> Result<(), E>
+    {
+        for i in self.iter() {
+            f(i)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the bits of the top layer which are set in both this set and
+    /// `other`.
+    ///
+    /// Every bit of the top layer covers a large block of indices, so this
+    /// is a cheap heuristic for how much two sets overlap: if it's zero the
+    /// sets are disjoint, and computing their intersection can be skipped.
+    /// The sets can still be disjoint when it isn't.
+    fn shared_layer3<B: BitSetLike>(&self, other: &B) -> usize
+        where Self: Sized
+    {
+        self.layer3() & other.layer3()
+    }
+
+    /// Returns `true` if every index of this set is also in `other`, which
+    /// is when the difference `self \ other` is empty.
+    ///
+    /// The non-empty words of this set are compared with `other` one at a
+    /// time, stopping at the first word with an index `other` lacks.
+    fn difference_is_empty<B: BitSetLike>(&self, other: &B) -> bool
+        where Self: Sized
+    {
+        let mut iter = self.iter();
+        while let Some((idx, word)) = iter.next_word() {
+            if word & !other.layer0(idx) != 0 {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Returns `true` if at least `threshold` indices are in exactly one of
+    /// this set and `other`.
+    ///
+    /// The words in which either set has members are compared one at a
+    /// time, and the comparison stops as soon as the number of differing
+    /// indices reaches `threshold`.
+    fn differs_by_at_least<B: BitSetLike>(&self, other: &B, threshold: usize) -> bool
+        where Self: Sized
+    {
+        let mut differing = 0;
+        let mut iter = BitSetOr(self, other).iter();
+        while differing < threshold {
+            match iter.next_word() {
+                Some((idx, _)) => {
+                    differing += (self.layer0(idx) ^ other.layer0(idx)).count_ones() as usize;
+                }
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Collects the maximal runs of consecutive indices in the set.
+    ///
+    /// Runs are merged across word boundaries, so every returned range
+    /// is separated from the next one by at least one missing index.
+    fn to_ranges(&self) -> Vec<RangeInclusive<Index>> {
+        Ranges::new(self).collect()
+    }
+
+    /// Reduces the maximal runs of consecutive indices in the set in
+    /// ascending order, like `Iterator::fold`.
+    ///
+    /// The runs are the ones [`to_ranges`] returns, but they are handed to
+    /// `f` one at a time without collecting them.
+    ///
+    /// [`to_ranges`]: #method.to_ranges
+    fn fold_ranges<B, F>(&self, init: B, f: F) -> B
+        where Self: Sized,
+              F: FnMut(B, RangeInclusive<Index>) -> B
+    {
+        Ranges::new(self).fold(init, f)
+    }
+
+    /// Returns the number of maximal runs of consecutive indices in the set,
+    /// which is the number of ranges [`to_ranges`] returns.
+    ///
+    /// Runs are counted by the indices that start them, which are found a
+    /// word at a time, carrying the highest bit of a word over to the next.
+    ///
+    /// [`to_ranges`]: #method.to_ranges
+    fn count_runs(&self) -> usize {
+        let mut iter = BitIter::new(self, [0, 0, 0, self.layer3()], [0; LAYERS - 1]);
+        let (mut runs, mut next) = (0, None);
+        while let Some((idx, word)) = iter.next_word() {
+            let carry = (next == Some((idx, true))) as usize;
+            runs += (word & !((word << 1) | carry)).count_ones() as usize;
+            next = Some((idx + 1, word >> ((1 << BITS) - 1) == 1));
+        }
+        runs
+    }
+
+    /// Like [`to_ranges`], but splits runs longer than `max_len` into
+    /// consecutive ranges of at most `max_len` indices each.
+    ///
+    /// Panics if `max_len` is zero.
+    ///
+    /// [`to_ranges`]: #method.to_ranges
+    fn to_ranges_capped(&self, max_len: Index) -> Vec<RangeInclusive<Index>> {
+        assert!(max_len > 0, "Expected a maximum range length above zero");
+        let mut ranges = Vec::new();
+        for range in Ranges::new(self) {
+            let (mut start, end) = range.into_inner();
+            while end - start >= max_len {
+                ranges.push(start..=start + (max_len - 1));
+                start += max_len;
+            }
+            ranges.push(start..=end);
+        }
+        ranges
+    }
+
+    /// Iterates over the maximal runs of consecutive indices like
+    /// [`to_ranges`], but skips every run holding fewer than `n` indices.
+    ///
+    /// [`to_ranges`]: #method.to_ranges
+    fn iter_runs_min_length(&self, n: Index) -> MinLengthRuns<&Self>
+        where Self: Sized
+    {
+        MinLengthRuns::new(self, n)
+    }
+
+    /// Create an iterator that will scan over the keyspace
+    ///
+    /// The indices are guaranteed to be yielded in strictly ascending
+    /// order. See [`collect_descending`] for the reverse order.
+    ///
+    /// [`collect_descending`]: #method.collect_descending
+    fn iter(self) -> BitIter<Self>
+        where Self: Sized
+    {
+        let layer3 = self.layer3();
+
+        BitIter::new(self, [0, 0, 0, layer3], [0; LAYERS - 1])
+    }
+
+    /// Replaces the contents of `out` with the indices of the set in
+    /// ascending order.
+    ///
+    /// The allocation of `out` is reused, and only grown when it can't
+    /// hold [`count`] indices, so calling this repeatedly with the same
+    /// vector avoids reallocating.
+    ///
+    /// [`count`]: #method.count
+    fn fill_sorted_vec(&self, out: &mut Vec<Index>) {
+        out.clear();
+        out.reserve(self.count());
+        out.extend(BitIter::new(self, [0, 0, 0, self.layer3()], [0; LAYERS - 1]));
+    }
+
+    /// Fills `out` with the lowest indices of the set in ascending order,
+    /// returning how many were written.
+    ///
+    /// This never allocates. To drain a large set into a fixed buffer over
+    /// several calls, keep an iterator around as a cursor and use
+    /// [`BitIter::drain_into_slice`] instead, which resumes where the
+    /// previous call stopped.
+    ///
+    /// [`BitIter::drain_into_slice`]: struct.BitIter.html#method.drain_into_slice
+    fn drain_into_slice(&self, out: &mut [Index]) -> usize {
+        BitIter::new(self, [0, 0, 0, self.layer3()], [0; LAYERS - 1]).drain_into_slice(out)
+    }
+
+    /// Collects the indices of the set in strictly descending order.
+    ///
+    /// The non-empty words of layer 0 are gathered in one pass, and then
+    /// unpacked from the highest bit of the last word downwards.
+    fn collect_descending(&self) -> Vec<Index> {
+        let mut iter = BitIter::new(self, [0, 0, 0, self.layer3()], [0; LAYERS - 1]);
+        let mut words = Vec::new();
+        let mut total = 0;
+        while let Some((idx, word)) = iter.next_word() {
+            total += word.count_ones() as usize;
+            words.push((idx, word));
+        }
+
+        let mut indices = Vec::with_capacity(total);
+        for &(idx, mut word) in words.iter().rev() {
+            while word != 0 {
+                let bit = (1 << BITS) - 1 - word.leading_zeros() as usize;
+                word &= !(1 << bit);
+                indices.push(((idx << BITS) | bit) as Index);
+            }
+        }
+        indices
+    }
+
+    /// Create an iterator that will scan over the keyspace, borrowing the
+    /// set instead of consuming it.
+    ///
+    /// This is the same as `(&set).iter()`.
+    ///
+    /// ```
+    /// use hibitset::{BitSet, BitSetAnd, BitSetLike};
+    ///
+    /// let a: BitSet = (0..10).collect();
+    /// let b: BitSet = (5..15).collect();
+    /// let and = BitSetAnd(&a, &b);
+    /// assert_eq!(and.iter_ref().collect::<Vec<_>>(), [5, 6, 7, 8, 9]);
+    /// assert_eq!(and.iter_ref().count(), 5);
+    /// ```
+    fn iter_ref(&self) -> BitIter<&Self>
+        where Self: Sized
+    {
+        self.iter()
+    }
+
+    /// Create an iterator that will scan over the keyspace, yielding the
+    /// indices in batches of `size`. Only the last batch may be shorter.
+    ///
+    /// Panics if `size` is zero.
+    fn iter_batched(self, size: usize) -> BatchedBitIter<Self>
+        where Self: Sized
+    {
+        BatchedBitIter::new(self.iter(), size)
+    }
+
+    /// Create an iterator which yields every index of the set together
+    /// with the word of layer 0 holding it and its bit within that word.
+    fn iter_with_context(self) -> ContextBitIter<Self>
+        where Self: Sized
+    {
+        ContextBitIter::new(self.iter())
+    }
+
+    /// Create an iterator over the indices of this set which are not in
+    /// `other`, in ascending order.
+    ///
+    /// Each word is masked as `self & !other` during the descent, which
+    /// only follows the upper layers of this set.
+    fn iter_difference<'a, B: BitSetLike>(&'a self, other: &'a B) -> BitIter<BitSetAnd<&'a Self, BitSetNot<&'a B>>>
+        where Self: Sized
+    {
+        BitSetAnd(self, BitSetNot(other)).iter()
+    }
+
+    /// Create an iterator over the indices of the set within
+    /// `base..base + len`, yielding each of them relative to `base`.
+    fn iter_offset(&self, base: Index, len: Index) -> OffsetBitIter<&Self>
+        where Self: Sized
+    {
+        OffsetBitIter::new(self, base, len)
+    }
+
+    /// Create an iterator over every pair of distinct indices `(i, j)` in
+    /// the set with `i < j`, in lexicographic order.
+    fn iter_pairs(&self) -> PairBitIter<&Self>
+        where Self: Sized
+    {
+        PairBitIter::new(self.iter())
+    }
+
+    /// Create an iterator over the indices within `range` which are not in
+    /// the set, in ascending order.
+    fn iter_absent_in_range(&self, range: Range<Index>) -> AbsentBitIter<&Self>
+        where Self: Sized
+    {
+        AbsentBitIter::new(self, range)
+    }
+
+    /// Create a parallel iterator that will scan over the keyspace
+    #[cfg(feature="parallel")]
+    fn par_iter(self) -> BitParIter<Self>
+        where Self: Sized
+    {
+        BitParIter::new(self)
+    }
+}
+
+/// A extension to the [`BitSetLike`] trait which allows draining it.
+pub trait DrainableBitSet: BitSetLike {
+    /// Removes bit from the bit set.
+    /// 
+    /// Returns `true` if removal happened and `false` otherwise.
+    fn remove(&mut self, i: Index) -> bool;
+
+    /// Create a draining iterator that will scan over the keyspace and clears it while doing so.
+    fn drain<'a>(&'a mut self) -> DrainBitIter<'a, Self>
+        where Self: Sized
+    {
+        let layer3 = self.layer3();
+
+        DrainBitIter::new(self, [0, 0, 0, layer3], [0; LAYERS - 1])
+    }
+}
+
+impl<'a, T> BitSetLike for &'a T
+    where T: BitSetLike + ?Sized
+{
+    #[inline]
+    fn layer3(&self) -> usize {
+        (*self).layer3()
+    }
+
+    #[inline]
+    fn layer2(&self, i: usize) -> usize {
+        (*self).layer2(i)
+    }
+
+    #[inline]
+    fn layer1(&self, i: usize) -> usize {
+        (*self).layer1(i)
+    }
+
+    #[inline]
+    fn layer0(&self, i: usize) -> usize {
+        (*self).layer0(i)
+    }
+
+    #[inline]
+    fn contains(&self, i: Index) -> bool {
+        (*self).contains(i)
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        (*self).is_empty()
+    }
+
+    #[inline]
+    fn count(&self) -> usize {
+        (*self).count()
+    }
+
+    #[inline]
+    fn count_at_least(&self, k: usize) -> bool {
+        (*self).count_at_least(k)
+    }
+
+    #[inline]
+    fn layer0_checked(&self, i: usize) -> Option<usize> {
+        (*self).layer0_checked(i)
+    }
+
+    #[inline]
+    fn max_word_index(&self) -> Option<usize> {
+        (*self).max_word_index()
+    }
+}
+
+impl<'a, T> BitSetLike for &'a mut T
+    where T: BitSetLike + ?Sized
+{
+    #[inline]
+    fn layer3(&self) -> usize {
+        (**self).layer3()
+    }
+
+    #[inline]
+    fn layer2(&self, i: usize) -> usize {
+        (**self).layer2(i)
+    }
+
+    #[inline]
+    fn layer1(&self, i: usize) -> usize {
+        (**self).layer1(i)
+    }
+
+    #[inline]
+    fn layer0(&self, i: usize) -> usize {
+        (**self).layer0(i)
+    }
+
+    #[inline]
+    fn contains(&self, i: Index) -> bool {
+        (**self).contains(i)
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        (**self).is_empty()
+    }
+
+    #[inline]
+    fn count(&self) -> usize {
+        (**self).count()
+    }
+
+    #[inline]
+    fn count_at_least(&self, k: usize) -> bool {
+        (**self).count_at_least(k)
+    }
+
+    #[inline]
+    fn layer0_checked(&self, i: usize) -> Option<usize> {
+        (**self).layer0_checked(i)
+    }
+
+    #[inline]
+    fn max_word_index(&self) -> Option<usize> {
+        (**self).max_word_index()
+    }
+}
+
+impl<'a, T> DrainableBitSet for &'a mut T
+    where T: DrainableBitSet + ?Sized
+{   
+    #[inline]
+    fn remove(&mut self, i: Index) -> bool {
+        (**self).remove(i)
+    }
+}
+
+impl<T> BitSetLike for Box<T>
+    where T: BitSetLike + ?Sized
+{
+    #[inline]
+    fn layer3(&self) -> usize {
+        (**self).layer3()
+    }
+
+    #[inline]
+    fn layer2(&self, i: usize) -> usize {
+        (**self).layer2(i)
+    }
+
+    #[inline]
+    fn layer1(&self, i: usize) -> usize {
+        (**self).layer1(i)
+    }
+
+    #[inline]
+    fn layer0(&self, i: usize) -> usize {
+        (**self).layer0(i)
+    }
+
+    #[inline]
+    fn contains(&self, i: Index) -> bool {
+        (**self).contains(i)
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        (**self).is_empty()
+    }
+
+    #[inline]
+    fn count(&self) -> usize {
+        (**self).count()
+    }
+
+    #[inline]
+    fn count_at_least(&self, k: usize) -> bool {
+        (**self).count_at_least(k)
+    }
+
+    #[inline]
+    fn layer0_checked(&self, i: usize) -> Option<usize> {
+        (**self).layer0_checked(i)
+    }
+
+    #[inline]
+    fn max_word_index(&self) -> Option<usize> {
+        (**self).max_word_index()
+    }
+}
+
+/// An optional set, where `None` is the empty set.
+impl<T: BitSetLike> BitSetLike for Option<T> {
+    #[inline]
+    fn layer3(&self) -> usize {
+        self.as_ref().map_or(0, |set| set.layer3())
+    }
+
+    #[inline]
+    fn layer2(&self, i: usize) -> usize {
+        self.as_ref().map_or(0, |set| set.layer2(i))
+    }
+
+    #[inline]
+    fn layer1(&self, i: usize) -> usize {
+        self.as_ref().map_or(0, |set| set.layer1(i))
+    }
+
+    #[inline]
+    fn layer0(&self, i: usize) -> usize {
+        self.as_ref().map_or(0, |set| set.layer0(i))
+    }
+
+    #[inline]
+    fn contains(&self, i: Index) -> bool {
+        match *self {
+            Some(ref set) => set.contains(i),
+            None => false,
+        }
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        match *self {
+            Some(ref set) => set.is_empty(),
+            None => true,
+        }
+    }
+
+    #[inline]
+    fn count(&self) -> usize {
+        self.as_ref().map_or(0, |set| set.count())
+    }
+
+    #[inline]
+    fn max_word_index(&self) -> Option<usize> {
+        self.as_ref().and_then(|set| set.max_word_index())
+    }
+}
+
+impl BitSetLike for BitSet {
+    #[inline]
+    fn layer3(&self) -> usize {
+        self.layer3
+    }
+
+    #[inline]
+    fn layer2(&self, i: usize) -> usize {
+        self.layer2.get(i).map(|&x| x).unwrap_or(0)
+    }
+
+    #[inline]
+    fn layer1(&self, i: usize) -> usize {
+        self.layer1.get(i).map(|&x| x).unwrap_or(0)
+    }
+
+    #[inline]
+    fn layer0(&self, i: usize) -> usize {
+        self.layer0.get(i).map(|&x| x).unwrap_or(0)
+    }
+
+    #[inline]
+    fn contains(&self, i: Index) -> bool {
+        self.contains(i)
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.layer3 == 0
+    }
+
+    #[inline]
+    fn count(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    fn count_at_least(&self, k: usize) -> bool {
+        self.len >= k
+    }
+
+    #[inline]
+    fn layer0_checked(&self, i: usize) -> Option<usize> {
+        self.layer0.get(i).cloned()
+    }
+
+    /// The upper layers of a `BitSet` only claim words which aren't empty,
+    /// so this follows their highest bits without backtracking.
+    #[inline]
+    fn max_word_index(&self) -> Option<usize> {
+        if self.layer3 == 0 {
+            return None;
+        }
+        let high = |word: usize| (1 << BITS) - 1 - word.leading_zeros() as usize;
+        let p2 = high(self.layer3);
+        let p1 = (p2 << BITS) | high(self.layer2[p2]);
+        Some((p1 << BITS) | high(self.layer1[p1]))
+    }
+}
+
+impl DrainableBitSet for BitSet {
+    #[inline]
+    fn remove(&mut self, i: Index) -> bool {
+        self.remove(i)
+    }
+}
+
+/// Checks if `set` is empty by looking for a non-empty word in layer 0.
+fn is_empty_by_descent<T: BitSetLike + ?Sized>(set: &T) -> bool {
+    BitIter::new(set, [0, 0, 0, set.layer3()], [0; LAYERS - 1]).next_word().is_none()
+}
+
+/// Tests each of `ids` for membership in `set`, reusing the last word of
+/// layer 0 while the ids stay within it.
+fn memberships<'a, T>(set: &'a T, ids: &'a [Index]) -> impl Iterator<Item = bool> + 'a
+    where T: BitSetLike + ?Sized
+{
+    let mut cached = None;
+    ids.iter().map(move |&id| {
+        let idx = id.offset(SHIFT1);
+        let word = match cached {
+            Some((cached_idx, word)) if cached_idx == idx => word,
+            _ => {
+                let word = set.layer0(idx);
+                cached = Some((idx, word));
+                word
+            }
+        };
+        word & id.mask(SHIFT0) != 0
+    })
+}
+
+/// Converts `range` into inclusive `(first, last)` indices, clamped to the
+/// indices a bit set is able to hold.
+///
+/// Returns `None` if the range doesn't cover any valid index.
+fn clamp_range(range: Range<Index>) -> Option<(usize, usize)> {
+    let end = ::std::cmp::min(range.end as usize, MAX_EID);
+    if (range.start as usize) < end {
+        Some((range.start as usize, end - 1))
+    } else {
+        None
+    }
+}
+
+/// Counts the indices of `set` which are within `range`.
+fn count_in_range<T: BitSetLike + ?Sized>(set: &T, range: Range<Index>) -> usize {
+    let mut count = 0;
+    if let Some((first, last)) = clamp_range(range) {
+        walk_range(set, LAYERS - 1, 0, first, last, &mut |_, word| {
+            count += word.count_ones() as usize;
+            true
+        });
+    }
+    count
+}
+
+/// Walks the non-empty words of layer 0 overlapping the indices
+/// `first..=last`, starting from word `idx` of `level`.
+///
+/// Only words whose summary bits are set are descended into, and bits
+/// outside of the range are masked off before a word is handed to `f`
+/// together with its index. The walk stops as soon as `f` returns `false`,
+/// which is then returned.
+fn walk_range<T, F>(set: &T, level: usize, idx: usize, first: usize, last: usize, f: &mut F) -> bool
+    where T: BitSetLike + ?Sized,
+          F: FnMut(usize, usize) -> bool
+{
+    let shift = BITS * level;
+    let base = idx << BITS;
+    let lo = ::std::cmp::max(first >> shift, base) - base;
+    let hi = ::std::cmp::min(last >> shift, base | ((1 << BITS) - 1)) - base;
+    let mut word = set.get_from_layer(level, idx) & (!0 << lo) & (!0 >> ((1 << BITS) - 1 - hi));
+
+    if level == 0 {
+        return word == 0 || f(idx, word);
+    }
+    while word != 0 {
+        let bit = word.trailing_zeros() as usize;
+        word &= !(1 << bit);
+        if !walk_range(set, level - 1, base | bit, first, last, f) {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BitSet, BitSetAnd, BitSetNot, BitSetLike};
+
+    #[test]
+    fn insert() {
+        let mut c = BitSet::new();
+        for i in 0..1_000 {
+            assert!(!c.add(i));
+            assert!(c.add(i));
+        }
+
+        for i in 0..1_000 {
+            assert!(c.contains(i));
+        }
+    }
+
+    #[test]
+    fn insert_newly() {
+        use std::collections::HashSet;
+
+        let mut c = BitSet::new();
+        let mut h = HashSet::new();
+        for i in 0..1_000 {
+            assert!(c.insert(i));
+            assert!(!c.insert(i));
+        }
+        for i in (0..2_000).map(|i| i * 7 % 1_500) {
+            assert_eq!(c.insert(i), h.insert(i) && i >= 1_000);
+        }
+        assert_eq!(c.cached_len(), 1_500);
+    }
+
+    #[test]
+    fn insert_100k() {
+        let mut c = BitSet::new();
+        for i in 0..100_000 {
+            assert!(!c.add(i));
+            assert!(c.add(i));
+        }
+
+        for i in 0..100_000 {
+            assert!(c.contains(i));
+        }
+    }
+    #[test]
+    fn remove() {
+        let mut c = BitSet::new();
+        for i in 0..1_000 {
+            assert!(!c.add(i));
+        }
+
+        for i in 0..1_000 {
+            assert!(c.contains(i));
+            assert!(c.remove(i));
+            assert!(!c.contains(i));
+            assert!(!c.remove(i));
+        }
+    }
+
+    #[test]
+    fn iter() {
+        let mut c = BitSet::new();
+        for i in 0..100_000 {
+            c.add(i);
+        }
+
+        let mut count = 0;
+        for (idx, i) in c.iter().enumerate() {
+            count += 1;
+            assert_eq!(idx, i as usize);
+        }
+        assert_eq!(count, 100_000);
+    }
+
+    #[test]
+    fn iter_odd_even() {
+        let mut odd = BitSet::new();
+        let mut even = BitSet::new();
+        for i in 0..100_000 {
+            if i % 2 == 1 {
+                odd.add(i);
+            } else {
+                even.add(i);
+            }
+        }
+
+        assert_eq!((&odd).iter().count(), 50_000);
+        assert_eq!((&even).iter().count(), 50_000);
+        assert_eq!(BitSetAnd(&odd, &even).iter().count(), 0);
+    }
+
+    #[test]
+    fn iter_random_add() {
+        use rand::{Rng, weak_rng};
+        let mut set = BitSet::new();
+        let mut rng = weak_rng();
+        let limit = 1_048_576;
+        let mut added = 0;
+        for _ in 0..(limit / 10) {
+            let index = rng.gen_range(0, limit);
+            if !set.add(index) {
+                added += 1;
+            }
+        }
+        assert_eq!(set.iter().count(), added as usize);
+    }
+
+    #[test]
+    fn iter_clusters() {
+        let mut set = BitSet::new();
+        for x in 0..8 {
+            let x = (x * 3) << (::BITS * 2); // scale to the last slot
+            for y in 0..8 {
+                let y = (y * 3) << (::BITS);
+                for z in 0..8 {
+                    let z = z * 2;
+                    set.add(x + y + z);
+                }
+            }
+        }
+        assert_eq!(set.iter().count(), 8usize.pow(3));
+    }
+
+    #[test]
+    fn not() {
+        let mut c = BitSet::new();
+        for i in 0..10_000 {
+            if i % 2 == 1 {
+                c.add(i);
+            }
+        }
+        let d = BitSetNot(c);
+        for (idx, i) in d.iter().take(5_000).enumerate() {
+            assert_eq!(idx * 2, i as usize);
+        }
+    }
+
+    #[test]
+    fn cached_len() {
+        use rand::{Rng, weak_rng};
+        use DrainableBitSet;
+
+        let mut rng = weak_rng();
+        let limit = 1_048_576;
+        let mut set = BitSet::new();
+        assert_eq!(set.cached_len(), 0);
+
+        for _ in 0..(limit / 10) {
+            let index = rng.gen_range(0, limit);
+            if rng.gen() {
+                set.add(index);
+            } else {
+                set.remove(index);
+            }
+        }
+        assert_eq!(set.cached_len(), (&set).iter().count());
+
+        let other: BitSet = (0..limit / 100).map(|_| rng.gen_range(0, limit)).collect();
+        set |= &other;
+        assert_eq!(set.cached_len(), (&set).iter().count());
+        set ^= &other;
+        assert_eq!(set.cached_len(), (&set).iter().count());
+        set |= &other;
+        set &= &BitSetNot(&other);
+        assert_eq!(set.cached_len(), (&set).iter().count());
+        set |= &other;
+        set &= &other;
+        assert_eq!(set.cached_len(), other.cached_len());
+
+        set.drain().take(100).for_each(|_| {});
+        assert_eq!(set.cached_len(), other.cached_len() - 100);
+
+        set.clear();
+        assert_eq!(set.cached_len(), 0);
+    }
+
+    #[test]
+    fn to_ranges() {
+        let mut set = BitSet::new();
+        for i in (5..10).chain(60..200).chain(4_094..4_097).chain(1_000_000..1_000_001) {
+            set.add(i);
+        }
+        let ranges = set.to_ranges();
+        assert_eq!(ranges, vec![5..=9, 60..=199, 4_094..=4_096, 1_000_000..=1_000_000]);
+        let rebuilt: BitSet = ranges.into_iter().flatten().collect();
+        assert_eq!(rebuilt.iter().collect::<Vec<_>>(), set.iter().collect::<Vec<_>>());
+
+        let checkerboard: BitSet = (0..1_000).filter(|i| i % 2 == 0).collect();
+        let ranges = checkerboard.to_ranges();
+        assert_eq!(ranges.len(), 500);
+        assert!(ranges.iter().all(|r| r.start() == r.end()));
+
+        let full: BitSet = (0..100_000).collect();
+        assert_eq!(full.to_ranges(), vec![0..=99_999]);
+        assert!(BitSet::new().to_ranges().is_empty());
+    }
+
+    #[test]
+    fn reflect() {
+        let set: BitSet = [0, 1].iter().collect();
+        assert_eq!(set.reflect(4).iter().collect::<Vec<_>>(), [2, 3]);
+
+        let set: BitSet = [3, 17, 64, 999, 1_000, 5_000].iter().collect();
+        assert_eq!(set.reflect(1_000).iter().collect::<Vec<_>>(), [0, 935, 982, 996]);
+        assert_eq!(
+            set.reflect(1_000).reflect(1_000).iter().collect::<Vec<_>>(),
+            [3, 17, 64, 999]
+        );
+        assert_eq!(set.reflect(0).iter().count(), 0);
+    }
+
+    #[test]
+    fn apply_permutation() {
+        let set: BitSet = (0..1_000).filter(|i| i % 3 == 0).chain(Some(5_000)).collect();
+
+        let identity = (0..6_000).collect::<Vec<_>>();
+        assert!(set.apply_permutation(&identity).iter().eq((&set).iter()));
+
+        let reverse = (0..6_000).rev().collect::<Vec<_>>();
+        let reversed = set.apply_permutation(&reverse);
+        assert!((&reversed).iter().eq(set.reflect(6_000).iter()));
+        assert!((0..6_000).all(|i| set.contains(i) == reversed.contains(5_999 - i)));
+        assert_eq!(reversed.cached_len(), set.cached_len());
+
+        // members past the end of the permutation are dropped
+        let short = set.apply_permutation(&identity[..999]);
+        assert!(short.iter().eq((0..999).filter(|i| i % 3 == 0)));
+        assert!(set.apply_permutation(&[]).is_empty());
+
+        let set: BitSet = [0, 1, 3].iter().collect();
+        let permuted = set.apply_permutation(&[9, 1_000_000, 4, 77]);
+        assert_eq!(permuted.iter().collect::<Vec<_>>(), [9, 77, 1_000_000]);
+    }
+
+    #[test]
+    fn windows() {
+        use count_in_range;
+
+        let set: BitSet = (0..10).collect();
+        let windows = set.windows(4, 2).collect::<Vec<_>>();
+        assert_eq!(windows, [(0, 4), (2, 4), (4, 4), (6, 4), (8, 2)]);
+        for &(start, count) in &windows {
+            assert_eq!(count, count_in_range(&set, start..start + 4));
+        }
+
+        let set: BitSet = (0..1_000).filter(|i| i % 7 == 0).chain(Some(500_000)).collect();
+        let windows = set.windows(100, 300).collect::<Vec<_>>();
+        assert_eq!(windows.len(), 500_000 / 300 + 1);
+        assert!(windows.iter().all(|&(start, count)| count == count_in_range(&set, start..start + 100)));
+        assert_eq!(windows.iter().map(|&(_, count)| count).sum::<usize>(), 15 + 15 + 14 + 14);
+
+        assert_eq!(BitSet::new().windows(4, 2).count(), 0);
+        let last: BitSet = [16_777_215].iter().collect();
+        assert_eq!(last.windows(10, 1 << 23).collect::<Vec<_>>(), [(0, 0), (1 << 23, 0)]);
+        assert_eq!(last.windows(1 << 23, 1 << 23).last(), Some((1 << 23, 1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected a window step above zero")]
+    fn windows_zero_step() {
+        BitSet::new().windows(4, 0);
+    }
+
+    #[test]
+    fn iter_while() {
+        let set: BitSet = (0..100).collect();
+        assert!(set.iter_while(|i| i < 50).eq(0..50));
+        assert_eq!(set.iter_while(|_| false).count(), 0);
+        assert!(set.iter_while(|_| true).eq(0..100));
+
+        // stops at the first rejected index, even if later ones would pass
+        let mut calls = 0;
+        assert!(set.iter_while(|i| { calls += 1; i % 10 != 7 }).eq(0..7));
+        assert_eq!(calls, 8);
+
+        // and stays exhausted afterwards
+        let mut iter = set.iter_while(|i| i != 3);
+        assert_eq!(iter.by_ref().count(), 3);
+        assert_eq!(iter.next(), None);
+
+        let and = BitSetAnd(&set, BitSetNot([3].iter().collect::<BitSet>()));
+        assert_eq!(and.iter_while(|i| i < 5).collect::<Vec<_>>(), [0, 1, 2, 4]);
+    }
+
+    #[test]
+    fn any_in_range() {
+        let set: BitSet = [10, 5_000, 300_000].iter().collect();
+        assert!(set.any_in_range(4_000..6_000));
+        assert!(set.any_in_range(5_000..5_001));
+        assert!(set.any_in_range(0..11));
+        assert!(set.any_in_range(299_999..16_777_216));
+        assert!(!set.any_in_range(11..5_000));
+        assert!(!set.any_in_range(5_001..300_000));
+        assert!(!set.any_in_range(300_001..u32::MAX));
+        assert!(!set.any_in_range(5_000..5_000));
+
+        // the upper layers of an intersection may claim bits that
+        // aren't present in layer 0.
+        let other: BitSet = [11, 5_001].iter().collect();
+        assert!(!BitSetAnd(&set, &other).any_in_range(0..10_000));
+        assert!(BitSetNot(&set).any_in_range(10..12));
+    }
+
+    #[test]
+    fn contains_any_all_of() {
+        let set: BitSet = (0..1_000).filter(|i| i % 2 == 0).chain(70_000..70_100).collect();
+        let queries: [&[u32]; 6] = [
+            &[],
+            &[2, 4, 6, 70_050],
+            &[2, 3, 4, 70_050],
+            &[1, 3, 5, 69_999, 70_100],
+            &[70_001, 70_000, 998, 1_000_000],
+            &[1_000_000],
+        ];
+        for ids in &queries {
+            assert_eq!(set.contains_any_of(ids), ids.iter().any(|&i| set.contains(i)));
+            assert_eq!(set.contains_all_of(ids), ids.iter().all(|&i| set.contains(i)));
+        }
+        assert!(BitSetNot(&set).contains_all_of(&[1, 3, 1_000_000]));
+    }
+
+    #[test]
+    fn iter_ref() {
+        use BitSetOr;
+
+        let a: BitSet = (0..100_000).filter(|i| i % 3 == 0).collect();
+        let b: BitSet = (50_000..200_000).filter(|i| i % 2 == 0).collect();
+        let expr = BitSetOr(BitSetAnd(&a, &b), BitSetNot(&a));
+        assert!(expr.iter_ref().take(1_000).eq((&expr).iter().take(1_000)));
+        assert!(a.iter_ref().eq((&a).iter()));
+        assert_eq!(a.iter_ref().count(), a.cached_len());
+    }
+
+    #[test]
+    fn count_at_least() {
+        use BitSetAll;
+
+        let set: BitSet = (0..10_000).filter(|i| i % 3 == 0).collect();
+        let and = BitSetAnd(&set, BitSetAll);
+        for &k in &[0, 1, 3_333, 3_334, 3_335, 100_000] {
+            assert_eq!(set.count_at_least(k), set.count() >= k);
+            assert_eq!(and.count_at_least(k), and.count() >= k);
+        }
+        // this would have to count every index of the keyspace
+        assert!(BitSetAnd(BitSetAll, BitSetAll).count_at_least(100));
+        assert!(!BitSetAnd(&set, BitSetNot(&set)).count_at_least(1));
+    }
+
+    #[test]
+    fn iteration_order() {
+        use rand::{Rng, weak_rng};
+
+        let mut rng = weak_rng();
+        let set: BitSet = (0..5_000).map(|_| rng.gen_range(0, 16_777_216)).chain(Some(0)).collect();
+        let ascending = (&set).iter().collect::<Vec<_>>();
+        assert!(ascending.windows(2).all(|w| w[0] < w[1]));
+
+        let descending = set.collect_descending();
+        assert!(descending.windows(2).all(|w| w[0] > w[1]));
+        assert_eq!(descending.len(), set.cached_len());
+        assert!(descending.iter().rev().eq(ascending.iter()));
+        assert_eq!(descending.last(), Some(&0));
+
+        let other: BitSet = (0..1_000).collect();
+        assert_eq!(BitSetAnd(&other, [5, 7, 999].iter().collect::<BitSet>()).collect_descending(), [999, 7, 5]);
+        assert!(BitSet::new().collect_descending().is_empty());
+    }
+
+    #[test]
+    fn shared_layer3() {
+        let block = 1 << 18;
+        let a: BitSet = [0, 5, 3 * block, 7 * block + 1].iter().collect();
+        let b: BitSet = [block, 2 * block + 9].iter().collect();
+        assert_eq!(a.shared_layer3(&b), 0);
+        assert!(BitSetAnd(&a, &b).is_empty());
+
+        let c: BitSet = [1, 7 * block + 100, 8 * block].iter().collect();
+        assert_eq!(a.shared_layer3(&c), 1 | 1 << 7);
+        assert_eq!(c.shared_layer3(&a), 1 | 1 << 7);
+        assert_eq!(a.shared_layer3(&a), a.layer3);
+        assert_eq!(a.shared_layer3(&BitSet::new()), 0);
+
+        // overlapping blocks don't imply shared indices
+        let d: BitSet = [6].iter().collect();
+        assert_eq!(a.shared_layer3(&d), 1);
+        assert!(BitSetAnd(&a, &d).is_empty());
+    }
+
+    #[test]
+    fn difference_is_empty() {
+        let set: BitSet = (0..1_000).filter(|i| i % 4 == 0).chain(500_000..500_010).collect();
+        let superset: BitSet = (0..1_000).filter(|i| i % 2 == 0).chain(499_990..500_100).collect();
+        assert!(set.difference_is_empty(&superset));
+        assert!(set.difference_is_empty(&set));
+        assert!(BitSet::new().difference_is_empty(&set));
+        assert!(!superset.difference_is_empty(&set));
+
+        for &extra in &[1, 501, 999, 500_100, 16_777_215] {
+            let mut other = set.clone();
+            other.add(extra);
+            assert!(!other.difference_is_empty(&superset), "{}", extra);
+            assert!(other.difference_is_empty(&BitSetNot(BitSet::new())));
+        }
+    }
+
+    #[test]
+    fn layer0_checked() {
+        let set: BitSet = [3, 200].iter().collect();
+        assert_eq!(set.layer0_checked(0), Some(1 << 3));
+        assert_eq!(set.layer0_checked(1), Some(0));
+        assert_eq!(set.layer0_checked(3), Some(1 << (200 - 192)));
+        assert_eq!(set.layer0_checked(4), None);
+        assert_eq!((&&set).layer0_checked(4), None);
+        assert_eq!(Box::new(set.clone()).layer0_checked(1), Some(0));
+        assert_eq!(BitSetNot(&set).layer0_checked(4), Some(!0));
+    }
+
+    #[test]
+    fn try_for_each() {
+        let set: BitSet = [4, 70, 300, 5_000, 1_000_000].iter().collect();
+        let mut visited = Vec::new();
+        assert_eq!(set.try_for_each(|i| -> Result<(), ()> {
+            visited.push(i);
+            Ok(())
+        }), Ok(()));
+        assert_eq!(visited, [4, 70, 300, 5_000, 1_000_000]);
+
+        let dynamic: &dyn BitSetLike = &set;
+        let mut calls = 0;
+        let result = (&dynamic).try_for_each(|i| {
+            calls += 1;
+            if calls == 3 { Err(i) } else { Ok(()) }
+        });
+        assert_eq!(result, Err(300));
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn iter_difference() {
+        use BitSetOr;
+
+        let a: BitSet = (0..100_000).filter(|i| i % 3 == 0).chain(Some(9_000_000)).collect();
+        let b: BitSet = (0..200_000).filter(|i| i % 2 == 0).collect();
+        assert!(a.iter_difference(&b).eq((&a).iter().filter(|&i| !b.contains(i))));
+        assert!(b.iter_difference(&a).eq((&b).iter().filter(|&i| !a.contains(i))));
+        assert_eq!(a.iter_difference(&a).count(), 0);
+
+        let or = BitSetOr(&b, BitSet::new());
+        assert!(or.iter_difference(&a).eq((&b).iter().filter(|&i| !a.contains(i))));
+    }
+
+    #[test]
+    fn drain_into_slice() {
+        let set: BitSet = (0..5_000).filter(|i| i % 3 == 0).chain(Some(900_000)).collect();
+
+        let mut out = [0; 7];
+        assert_eq!(set.drain_into_slice(&mut out), 7);
+        assert_eq!(out, [0, 3, 6, 9, 12, 15, 18]);
+        assert_eq!(set.drain_into_slice(&mut []), 0);
+
+        let mut cursor = (&set).iter();
+        let mut drained = Vec::new();
+        loop {
+            let n = cursor.drain_into_slice(&mut out);
+            drained.extend_from_slice(&out[..n]);
+            if n < out.len() {
+                break;
+            }
+        }
+        assert!(drained.iter().cloned().eq((&set).iter()));
+        assert_eq!(cursor.drain_into_slice(&mut out), 0);
+
+        let mut out = [0; 8];
+        assert_eq!(BitSet::new().drain_into_slice(&mut out), 0);
+        let small: BitSet = [4, 70].iter().collect();
+        assert_eq!(small.drain_into_slice(&mut out), 2);
+        assert_eq!(out[..2], [4, 70]);
+    }
+
+    #[test]
+    fn fill_sorted_vec() {
+        let mut out = vec![7; 3];
+        let set: BitSet = (0..5_000).filter(|i| i % 2 == 0).collect();
+        set.fill_sorted_vec(&mut out);
+        assert!(out.iter().cloned().eq((0..5_000).filter(|i| i % 2 == 0)));
+        let capacity = out.capacity();
+
+        let set: BitSet = [3, 900_000].iter().collect();
+        set.fill_sorted_vec(&mut out);
+        assert_eq!(out, [3, 900_000]);
+        assert_eq!(out.capacity(), capacity);
+
+        BitSetAnd(&set, BitSetNot(BitSet::new())).fill_sorted_vec(&mut out);
+        assert_eq!(out, [3, 900_000]);
+    }
+
+    #[test]
+    fn differs_by_at_least() {
+        let a: BitSet = (0..10_000).filter(|i| i % 3 == 0).collect();
+        assert!(a.differs_by_at_least(&a.clone(), 0));
+        for &threshold in &[1, 2, 100] {
+            assert!(!a.differs_by_at_least(&a.clone(), threshold));
+        }
+
+        let mut b = a.clone();
+        b.remove(3);
+        b.add(4);
+        b.add(500_000);
+        assert!(a.differs_by_at_least(&b, 3));
+        assert!(!a.differs_by_at_least(&b, 4));
+        assert!(b.differs_by_at_least(&a, 3));
+        assert!(a.differs_by_at_least(&BitSet::new(), a.cached_len()));
+        assert!(!a.differs_by_at_least(&BitSet::new(), a.cached_len() + 1));
+    }
+
+    #[test]
+    fn optional_set() {
+        let set: BitSet = (0..1_000).collect();
+        let mask: BitSet = (500..2_000).filter(|i| i % 2 == 0).collect();
+
+        assert_eq!(BitSetAnd(&set, None::<BitSet>).iter().count(), 0);
+        assert!(BitSetAnd(&set, Some(&mask)).iter().eq(BitSetAnd(&set, &mask).iter()));
+        assert!(::BitSetOr(&set, None::<&BitSet>).iter().eq((&set).iter()));
+
+        assert!(None::<BitSet>.is_empty());
+        assert_eq!(None::<BitSet>.count(), 0);
+        assert!(!None::<BitSet>.contains(3));
+        assert!(!Some(&mask).is_empty());
+        assert_eq!(Some(&mask).count(), mask.cached_len());
+        assert!(Some(&mask).contains(600));
+    }
+
+    #[test]
+    fn from_iter_bounded() {
+        let ids = [16_777_216, 3, 16_777_215, u32::MAX, 900, 20_000_000];
+        let set = BitSet::from_iter_bounded(ids.iter().cloned());
+        assert_eq!((&set).iter().collect::<Vec<_>>(), [3, 900, 16_777_215]);
+        assert_eq!(set.cached_len(), 3);
+
+        assert!(BitSet::from_iter_bounded(vec![u32::MAX, 16_777_216]).is_empty());
+        assert!(BitSet::from_iter_bounded(0..100).iter().eq(0..100));
+    }
+
+    #[test]
+    fn density() {
+        assert_eq!(BitSet::new().density(), 0.0);
+        assert_eq!((0..100).collect::<BitSet>().density(), 1.0);
+        assert_eq!((0..100).filter(|i| i % 4 == 3).collect::<BitSet>().density(), 0.25);
+        assert_eq!([0].iter().collect::<BitSet>().density(), 1.0);
+
+        let high: BitSet = [9_999_999].iter().collect();
+        assert_eq!(high.density(), 1.0 / 10_000_000.0);
+
+        // removed members don't count towards the occupied range
+        let mut set: BitSet = [1, 3, 1_000_000].iter().collect();
+        set.remove(1_000_000);
+        assert_eq!(set.density(), 0.5);
+    }
+
+    #[test]
+    fn iter_changed_since() {
+        let previous: BitSet = (0..1_000).filter(|i| i % 3 == 0).chain(Some(500_000)).collect();
+        let mut current = previous.clone();
+        for &i in &[1, 2, 999, 700_000] {
+            current.add(i);
+        }
+        for &i in &[0, 300, 500_000] {
+            current.remove(i);
+        }
+
+        assert_eq!(current.iter_added_since(&previous).collect::<Vec<_>>(), [1, 2, 700_000]);
+        assert_eq!(current.iter_removed_since(&previous).collect::<Vec<_>>(), [0, 300, 500_000]);
+        assert_eq!(current.iter_added_since(&current).count(), 0);
+        assert_eq!(previous.iter_removed_since(&previous).count(), 0);
+
+        // applying the delta to the baseline gives the current frame
+        let mut rebuilt = previous.clone();
+        for i in current.iter_added_since(&previous) {
+            rebuilt.add(i);
+        }
+        for i in current.iter_removed_since(&previous) {
+            rebuilt.remove(i);
+        }
+        assert!((&rebuilt).iter().eq((&current).iter()));
+
+        let empty = BitSet::new();
+        assert!(current.iter_added_since(&empty).eq((&current).iter()));
+        assert!(empty.iter_removed_since(&current).eq((&current).iter()));
+    }
+
+    #[test]
+    fn combinators() {
+        use {BitSetOr, BitSetXor};
+
+        let a: BitSet = (0..1_000).filter(|i| i % 2 == 0).chain(Some(500_000)).collect();
+        let b: BitSet = (0..2_000).filter(|i| i % 3 == 0).collect();
+
+        assert!(a.and(&b).iter().eq(BitSetAnd(&a, &b).iter()));
+        assert!(a.or(&b).iter().eq(BitSetOr(&a, &b).iter()));
+        assert!(a.not().iter().take(1_000).eq(BitSetNot(&a).iter().take(1_000)));
+        assert!(!a.not().contains(500_000) && a.not().contains(1));
+
+        // the combinators take any set, so they can be nested
+        let c = BitSetXor(&a, &b);
+        assert!(a.and(&c).iter().eq(BitSetAnd(&a, &c).iter()));
+        assert!(a.and(&b.not()).iter().eq((&a).iter().filter(|&i| !b.contains(i))));
+        assert_eq!(a.and(&a.or(&b)).iter().count(), a.cached_len());
+    }
+
+    #[test]
+    fn equals_hashset() {
+        use std::collections::HashSet;
+
+        let ids = (0..5_000).filter(|i| i % 7 == 0).chain(Some(10_000_000)).collect::<Vec<_>>();
+        let set: BitSet = ids.iter().collect();
+        let mut hash = ids.iter().cloned().collect::<HashSet<u32>>();
+        assert!(set.equals_hashset(&hash));
+        assert!(BitSet::new().equals_hashset(&HashSet::new()));
+
+        hash.remove(&7);
+        assert!(!set.equals_hashset(&hash));
+        hash.insert(8);
+        assert!(!set.equals_hashset(&hash));
+        hash.remove(&8);
+        hash.insert(7);
+
+        // Same count, but with an index past the end of every layer.
+        hash.remove(&10_000_000);
+        hash.insert(u32::MAX);
+        assert_eq!(hash.len(), set.cached_len());
+        assert!(!set.equals_hashset(&hash));
+    }
+
+    #[test]
+    fn density_profile() {
+        let uniform: BitSet = (0..::util::MAX_EID as u32).filter(|i| i % 1_000 == 0).collect();
+        let profile = uniform.density_profile(8);
+        assert_eq!(profile.len(), 8);
+        assert_eq!(profile.iter().sum::<usize>(), uniform.cached_len());
+        for &count in &profile {
+            assert!((2_096..2_099).contains(&count));
+        }
+
+        let clustered: BitSet = (9_000_000..9_010_000).collect();
+        assert_eq!(clustered.density_profile(4), [0, 0, 10_000, 0]);
+        assert_eq!(clustered.density_profile(1), [10_000]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected a bucket count above zero")]
+    fn density_profile_zero_buckets() {
+        BitSet::new().density_profile(0);
+    }
+
+    #[test]
+    fn compact_indices() {
+        use rand::{Rng, weak_rng};
+
+        let mut rng = weak_rng();
+        let set: BitSet = (0..10_000).map(|_| rng.gen_range(0, 1_048_576)).collect();
+        let dense = set.compact_indices();
+        assert_eq!(dense.len(), set.cached_len());
+        for id in &set {
+            assert_eq!(dense[set.dense_index_of(id).unwrap()], id);
+        }
+
+        let set: BitSet = [0, 64, 65].iter().collect();
+        assert_eq!(set.compact_indices(), [0, 64, 65]);
+        assert_eq!(set.dense_index_of(0), Some(0));
+        assert_eq!(set.dense_index_of(65), Some(2));
+        assert_eq!(set.dense_index_of(1), None);
+    }
+
+    #[test]
+    fn is_empty() {
+        use BitSetOr;
+
+        let empty = BitSet::new();
+        let mut a = BitSet::new();
+        a.add(3);
+        let mut b = BitSet::new();
+        b.add(4);
+
+        assert!(empty.is_empty());
+        assert!(!a.is_empty());
+        assert!(BitSetOr(&empty, &empty).is_empty());
+        assert!(!BitSetOr(&empty, &a).is_empty());
+        assert!(BitSetAnd(&a, &empty).is_empty());
+        assert!(!BitSetAnd(&a, &a).is_empty());
+
+        // both operands share every upper layer bit, but nothing in layer 0.
+        let and = BitSetAnd(&a, &b);
+        assert!(and.layer3() != 0 && and.layer1(0) != 0);
+        assert!(and.is_empty());
+        assert!(BitSetOr(&and, &empty).is_empty());
+        assert!(!BitSetNot(&and).is_empty());
+
+        a.remove(3);
+        assert!(a.is_empty());
+    }
+
+    #[test]
+    fn max_word_index() {
+        use BitSetOr;
+
+        let mut set: BitSet = [5_000, 70_000].iter().collect();
+        assert_eq!(set.max_word_index(), Some(70_000 >> ::BITS));
+        set.remove(70_000);
+        assert_eq!(set.max_word_index(), Some(5_000 >> ::BITS));
+        assert_eq!(BitSet::new().max_word_index(), None);
+        assert_eq!([16_777_215].iter().collect::<BitSet>().max_word_index(), Some(262_143));
+
+        let other: BitSet = [3, 900_000].iter().collect();
+        assert_eq!(BitSetOr(&set, &other).max_word_index(), Some(900_000 >> ::BITS));
+        assert_eq!(BitSetOr(&other, &set).max_word_index(), Some(900_000 >> ::BITS));
+        assert_eq!(BitSetOr(&set, BitSet::new()).max_word_index(), Some(5_000 >> ::BITS));
+
+        // the upper layers of the intersection claim the word of 900_000,
+        // which is empty, so the descent has to backtrack.
+        let mut near: BitSet = [3, 900_001].iter().collect();
+        assert_eq!(BitSetAnd(&other, &near).max_word_index(), Some(0));
+        near.remove(3);
+        assert_eq!(BitSetAnd(&other, &near).max_word_index(), None);
+        assert_eq!(Some(&other).max_word_index(), Some(900_000 >> ::BITS));
+        assert_eq!(None::<BitSet>.max_word_index(), None);
+    }
+
+    #[test]
+    fn first_set_word() {
+        let mut set = BitSet::new();
+        assert_eq!(set.first_set_word(), None);
+
+        set.add(70_000);
+        set.add(5_000);
+        assert_eq!(set.first_set_word(), Some(5_000 >> ::BITS));
+
+        set.remove(5_000);
+        assert_eq!(set.first_set_word(), Some(70_000 >> ::BITS));
+
+        let other: BitSet = [5_001, 70_000].iter().collect();
+        assert_eq!(BitSetAnd(&set, &other).first_set_word(), Some(70_000 >> ::BITS));
+    }
+
+    #[test]
+    fn union_all() {
+        use rand::{Rng, weak_rng};
+        use BitSetOr;
+
+        let mut rng = weak_rng();
+        let sets = [10, 1_000, 100_000, 1_048_576]
+            .iter()
+            .map(|&limit| (0..1_000).map(|_| rng.gen_range(0, limit)).collect::<BitSet>())
+            .collect::<Vec<_>>();
+        let refs = sets.iter().collect::<Vec<_>>();
+
+        let union = BitSet::union_all(&refs);
+        let expected = refs.iter().fold(BitSet::new(), |acc, set| {
+            BitSetOr(&acc, *set).iter().collect()
+        });
+        assert_eq!(union.cached_len(), expected.cached_len());
+        assert_eq!(union.iter().collect::<Vec<_>>(), expected.iter().collect::<Vec<_>>());
+
+        let three = BitSetOr(BitSetOr(refs[0], refs[1]), refs[2]);
+        let union = BitSet::union_all(&refs[..3]);
+        assert_eq!(union.iter().collect::<Vec<_>>(), three.iter().collect::<Vec<_>>());
+
+        assert!(BitSet::union_all(&[]).is_empty());
+    }
+
+    #[test]
+    fn intersect_all() {
+        use rand::{Rng, weak_rng};
+
+        let mut rng = weak_rng();
+        let sets = [1_000, 1_500, 2_000, 4_000]
+            .iter()
+            .map(|&limit| (0..2_000).map(|_| rng.gen_range(0, limit)).collect::<BitSet>())
+            .collect::<Vec<_>>();
+        let refs = sets.iter().collect::<Vec<_>>();
+
+        let expected = BitSetAnd(BitSetAnd(refs[0], refs[1]), BitSetAnd(refs[2], refs[3]));
+        let intersection = BitSet::intersect_all(&refs);
+        assert!(intersection.cached_len() > 0);
+        assert_eq!(
+            intersection.iter().collect::<Vec<_>>(),
+            expected.iter().collect::<Vec<_>>()
+        );
+
+        // an empty input ends every word early
+        let empty = BitSet::new();
+        let mut with_empty = refs.clone();
+        with_empty.insert(1, &empty);
+        assert!(BitSet::intersect_all(&with_empty).is_empty());
+
+        let disjoint: BitSet = (0..1_000).map(|i| i * 2 + 1).collect();
+        let evens: BitSet = (0..1_000).map(|i| i * 2).collect();
+        assert!(BitSet::intersect_all(&[&evens, &disjoint, &evens]).is_empty());
+        assert!(BitSet::intersect_all(&[]).is_empty());
+    }
+
+    #[test]
+    fn copy_from_atomic() {
+        use std::sync::Arc;
+        use std::thread;
+        use AtomicBitSet;
+
+        let atomic = Arc::new(AtomicBitSet::new());
+        let workers = (0..4)
+            .map(|n| {
+                let atomic = atomic.clone();
+                thread::spawn(move || {
+                    for i in 0..10_000 {
+                        atomic.add_atomic(i * 97 % 1_000_000 + n);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let mut set: BitSet = (0..1_048_576).collect();
+        let capacity = set.layer0.capacity();
+        set.copy_from_atomic(&atomic);
+        assert_eq!(set.layer0.capacity(), capacity);
+        assert_eq!(set.cached_len(), (&*atomic).iter().count());
+        assert_eq!(
+            (&set).iter().collect::<Vec<_>>(),
+            (&*atomic).iter().collect::<Vec<_>>()
+        );
+
+        set.copy_from_atomic(&AtomicBitSet::new());
+        assert!(set.is_empty());
+        assert_eq!(set.cached_len(), 0);
+    }
+
+    #[test]
+    fn find() {
+        let set: BitSet = [3, 70, 5_000, 9_000, 100_000].iter().collect();
+        let mut visited = 0;
+        assert_eq!(set.find(|i| {
+            visited += 1;
+            i > 1_000
+        }), Some(5_000));
+        assert_eq!(visited, 3);
+        assert_eq!(set.find(|i| i % 2 == 1), Some(3));
+        assert_eq!(set.find(|i| i > 100_000), None);
+
+        let boxed: Box<dyn BitSetLike> = Box::new(set);
+        assert_eq!(boxed.find(|i| i % 3_000 == 0), Some(9_000));
+        let dynamic: &dyn BitSetLike = &*boxed;
+        assert_eq!((&dynamic).find(|i| i == 70), Some(70));
+    }
+
+    #[test]
+    fn fold_ranges() {
+        let sets: Vec<BitSet> = vec![
+            BitSet::new(),
+            (5..10).chain(60..200).chain(Some(1_000)).collect(),
+            (0..100_000).filter(|i| i % 64 != 0 && i % 7 != 3).chain(Some(16_777_215)).collect(),
+        ];
+        for set in &sets {
+            let covered = set.fold_ranges(0, |len, range| len + (range.end() - range.start() + 1) as usize);
+            assert_eq!(covered, set.count());
+            assert_eq!(set.fold_ranges(0, |runs, _| runs + 1), set.count_runs());
+            let ranges = set.fold_ranges(Vec::new(), |mut ranges, range| {
+                ranges.push(range);
+                ranges
+            });
+            assert_eq!(ranges, set.to_ranges());
+        }
+    }
+
+    #[test]
+    fn count_runs() {
+        let set: BitSet = (5..10).chain(60..200).chain(Some(1_000)).collect();
+        assert_eq!(set.count_runs(), 3);
+        assert_eq!((0..100_000).collect::<BitSet>().count_runs(), 1);
+        assert_eq!(BitSet::new().count_runs(), 0);
+
+        let checkerboard: BitSet = (0..1_000).filter(|i| i % 2 == 0).collect();
+        assert_eq!(checkerboard.count_runs(), 500);
+
+        // runs touching word boundaries, and words that are only adjacent
+        // through the upper layers
+        for set in &[
+            (62..66).chain(Some(127)).chain(Some(128)).chain(Some(4_095)).chain(Some(4_096)).collect::<BitSet>(),
+            [63, 64, 191, 262_143, 262_144, 16_777_215].iter().collect(),
+            (0..100_000).filter(|i| i % 64 != 0 && i % 7 != 3).collect(),
+        ] {
+            assert_eq!(set.count_runs(), set.to_ranges().len());
+        }
+        let and = BitSetAnd(&checkerboard, BitSetNot([2].iter().collect::<BitSet>()));
+        assert_eq!(and.count_runs(), 499);
+    }
+
+    #[test]
+    fn iter_runs_min_length() {
+        let set: BitSet = [3, 10, 11, 40].iter().cloned().chain(60..65).chain(Some(1_000)).collect();
+        assert_eq!(set.iter_runs_min_length(3).collect::<Vec<_>>(), vec![60..=64]);
+        assert_eq!(set.iter_runs_min_length(2).collect::<Vec<_>>(), vec![10..=11, 60..=64]);
+        assert_eq!(set.iter_runs_min_length(0).count(), set.to_ranges().len());
+        assert_eq!(set.iter_runs_min_length(6).count(), 0);
+
+        // runs spanning word boundaries are measured as a whole
+        let set: BitSet = (62..66).chain(Some(200)).collect();
+        assert_eq!(set.iter_runs_min_length(4).collect::<Vec<_>>(), vec![62..=65]);
+    }
+
+    #[test]
+    fn to_ranges_capped() {
+        let set: BitSet = (100..1_100).chain(2_000..2_003).chain(3_000..3_256).collect();
+        assert_eq!(
+            set.to_ranges_capped(256),
+            vec![
+                100..=355,
+                356..=611,
+                612..=867,
+                868..=1_099,
+                2_000..=2_002,
+                3_000..=3_255,
+            ]
+        );
+        assert_eq!(set.to_ranges_capped(10_000), set.to_ranges());
+        assert_eq!(set.to_ranges_capped(1).len(), set.cached_len());
+    }
+
+    #[test]
+    fn invert() {
+        let mut set: BitSet = (0..10).filter(|i| i % 2 == 0).collect();
+        set.invert(10);
+        assert_eq!((&set).iter().collect::<Vec<_>>(), [1, 3, 5, 7, 9]);
+        assert_eq!(set.cached_len(), 5);
+
+        let original: BitSet = (0..5_000).filter(|i| i % 3 == 0).chain(Some(300_000)).collect();
+        for &bound in &[0, 1, 63, 64, 65, 4_999, 5_000, 300_000, 300_001, 1_000_000, 16_777_216] {
+            let mut set = original.clone();
+            set.invert(bound);
+            let expected = ::BitSetNotBounded(&original, bound).iter().collect::<BitSet>();
+            assert!((&set).iter().eq((&expected).iter()), "{}", bound);
+            assert_eq!(set.cached_len(), expected.cached_len());
+
+            set.invert(bound);
+            assert!((&set).iter().eq((&original).iter().filter(|&i| i < bound)), "{}", bound);
+            assert_eq!(set.is_empty(), bound == 0);
+        }
+    }
+
+    #[test]
+    fn toggle_range() {
+        let original: BitSet = (0..10_000).filter(|i| i % 3 == 0).chain(200_000..200_100).collect();
+        let mut set = original.clone();
+
+        set.toggle_range(50..150_000);
+        assert_eq!(set.cached_len(), (&set).iter().count());
+        for i in 0..200_100 {
+            assert_eq!(set.contains(i), original.contains(i) ^ (50..150_000).contains(&i));
+        }
+        set.toggle_range(50..150_000);
+        assert_eq!((&set).iter().collect::<Vec<_>>(), (&original).iter().collect::<Vec<_>>());
+        assert_eq!(set.cached_len(), original.cached_len());
+
+        set.toggle_range(300_000..300_130);
+        assert_eq!(set.to_ranges().last(), Some(&(300_000..=300_129)));
+        set.toggle_range(300_000..300_130);
+        set.toggle_range(200_000..200_100);
+        set.toggle_range(0..10_000);
+        set.toggle_range(0..10_000);
+        set.toggle_range(5..5);
+        assert_eq!(set.cached_len(), 3_334);
+        assert_eq!(set.layer3, 1);
+    }
+
+    #[test]
+    fn layer_lengths() {
+        use util::{SHIFT1, SHIFT2, SHIFT3};
+
+        assert_eq!(BitSet::new().layer_lengths(), [0, 0, 0, 1]);
+
+        let mut set = BitSet::with_capacity(100_000);
+        let expected = [
+            (100_000 >> SHIFT1) + 1,
+            (100_000 >> SHIFT2) + 1,
+            (100_000 >> SHIFT3) + 1,
+            1,
+        ];
+        assert_eq!(set.layer_lengths(), expected);
+
+        set.add(5);
+        set.remove(5);
+        assert_eq!(set.layer_lengths(), expected);
+        set.add(1_000_000);
+        assert_eq!(set.layer_lengths()[0], (1_000_000 >> SHIFT1) + 1);
+    }
+
+    #[test]
+    fn canonical_cmp() {
+        use std::cmp::Ordering::*;
+
+        let sets: Vec<BitSet> = vec![
+            BitSet::new(),
+            [0].iter().collect(),
+            [0, 1].iter().collect(),
+            [0, 1, 100_000].iter().collect(),
+            [0, 2].iter().collect(),
+            [1].iter().collect(),
+            [70_000].iter().collect(),
+        ];
+        for (i, a) in sets.iter().enumerate() {
+            for (j, b) in sets.iter().enumerate() {
+                assert_eq!(a.canonical_cmp(b), i.cmp(&j));
+                assert_eq!(a.canonical_cmp(b), b.canonical_cmp(a).reverse());
+                for c in &sets {
+                    if a.canonical_cmp(b) == Less && b.canonical_cmp(c) == Less {
+                        assert_eq!(a.canonical_cmp(c), Less);
+                    }
+                }
+            }
+        }
+
+        let mut a = BitSet::with_capacity(1_000_000);
+        a.add(5);
+        let b: BitSet = [5].iter().collect();
+        assert_eq!(a.canonical_cmp(&b), Equal);
+
+        let mut shuffled = [sets[4].clone(), sets[0].clone(), sets[6].clone(), sets[2].clone()];
+        shuffled.sort_by(|a, b| a.canonical_cmp(b));
+        let sorted = shuffled.iter().map(|set| set.iter().collect::<Vec<_>>()).collect::<Vec<_>>();
+        assert_eq!(sorted, vec![vec![], vec![0, 1], vec![0, 2], vec![70_000]]);
     }
-}
 
-impl<'a, T> DrainableBitSet for &'a mut T
-    where T: DrainableBitSet
-{   
-    #[inline]
-    fn remove(&mut self, i: Index) -> bool {
-        (**self).remove(i)
+    #[test]
+    fn from_layer0() {
+        use rand::{Rng, weak_rng};
+
+        let mut rng = weak_rng();
+        let mut expected = BitSet::new();
+        let mut words = vec![0usize; 1 << (3 * ::BITS)];
+        for _ in 0..10_000 {
+            let index: u32 = rng.gen_range(0, 1_048_576);
+            expected.add(index);
+            words[index as usize >> ::BITS] |= 1 << (index as usize & ((1 << ::BITS) - 1));
+        }
+
+        let set = BitSet::from_layer0(words);
+        assert_eq!(set.layer3, expected.layer3);
+        for i in 0..expected.layer2.len() {
+            assert_eq!(set.layer2[i], expected.layer2[i]);
+        }
+        for i in 0..expected.layer1.len() {
+            assert_eq!(set.layer1[i], expected.layer1[i]);
+        }
+        assert_eq!(set.cached_len(), expected.cached_len());
+        assert_eq!((&set).iter().collect::<Vec<_>>(), expected.iter().collect::<Vec<_>>());
+
+        let set = BitSet::from_layer0(vec![0, 0b101, 0]);
+        assert_eq!(set.iter().collect::<Vec<_>>(), [1 << ::BITS, (1 << ::BITS) + 2]);
+        assert!(BitSet::from_layer0(vec![]).is_empty());
     }
-}
 
-impl BitSetLike for BitSet {
-    #[inline]
-    fn layer3(&self) -> usize {
-        self.layer3
+    #[test]
+    fn remove_all() {
+        use rand::{Rng, weak_rng};
+
+        let mut rng = weak_rng();
+        let a: BitSet = (0..50_000).map(|_| rng.gen_range(0, 1_048_576)).collect();
+        let b: BitSet = (0..50_000).map(|_| rng.gen_range(0, 1_048_576)).collect();
+        let mut target: BitSet = (0..200_000).map(|_| rng.gen_range(0, 1_048_576)).collect();
+        let expected = (&target).iter().filter(|&i| !(a.contains(i) && b.contains(i))).collect::<Vec<_>>();
+
+        target.remove_all(&BitSetAnd(&a, &b));
+        assert_eq!(target.cached_len(), expected.len());
+        assert_eq!((&target).iter().collect::<Vec<_>>(), expected);
+
+        target.remove_all(&BitSetNot(BitSet::new()));
+        assert!(target.is_empty());
+        assert_eq!(target.cached_len(), 0);
+        target.add(3);
+        assert_eq!(target.iter().collect::<Vec<_>>(), [3]);
     }
 
-    #[inline]
-    fn layer2(&self, i: usize) -> usize {
-        self.layer2.get(i).map(|&x| x).unwrap_or(0)
+    #[test]
+    fn clone_range() {
+        let set: BitSet = (0..300_000).filter(|i| i % 7 == 0).collect();
+        for &(a, b) in &[(0, 0), (10, 20), (65, 4_200), (1_000, 299_999), (250_000, 1 << 30)] {
+            let clone = set.clone_range(a..b);
+            let expected: Vec<_> = (&set).iter().filter(|&i| (a..b).contains(&i)).collect();
+            assert_eq!((&clone).iter().collect::<Vec<_>>(), expected);
+            assert_eq!(clone.cached_len(), expected.len());
+        }
+
+        let clone = set.clone_range(700..1_400);
+        assert!(!clone.contains(693));
+        assert!(clone.contains(700));
+        assert!(!clone.contains(1_400));
+        assert!(set.contains(1_400));
+        assert!(set.clone_range(5..5).is_empty());
     }
 
-    #[inline]
-    fn layer1(&self, i: usize) -> usize {
-        self.layer1.get(i).map(|&x| x).unwrap_or(0)
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Upper layers are not allocated for index 100")]
+    fn add_inconsistent_layers() {
+        let mut set = BitSet {
+            layer0: vec![0; 4],
+            ..BitSet::new()
+        };
+        set.add(100);
     }
 
-    #[inline]
-    fn layer0(&self, i: usize) -> usize {
-        self.layer0.get(i).map(|&x| x).unwrap_or(0)
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Upper layers are not allocated for index 3")]
+    fn remove_inconsistent_layers() {
+        let mut set = BitSet {
+            layer0: vec![1 << 3],
+            len: 1,
+            ..BitSet::new()
+        };
+        set.remove(3);
     }
 
-    #[inline]
-    fn contains(&self, i: Index) -> bool {
-        self.contains(i)
+    #[test]
+    fn swap_contents() {
+        let mut a: BitSet = (0..100).collect();
+        let mut b: BitSet = [5, 500_000].iter().collect();
+        a.swap_contents(&mut b);
+        assert_eq!((&a).iter().collect::<Vec<_>>(), [5, 500_000]);
+        assert!((&b).iter().eq(0..100));
+        assert_eq!((a.cached_len(), b.cached_len()), (2, 100));
     }
-}
 
-impl DrainableBitSet for BitSet {
-    #[inline]
-    fn remove(&mut self, i: Index) -> bool {
-        self.remove(i)
+    #[test]
+    fn swap_keep_capacity() {
+        let mut a = BitSet::with_capacity(100_000);
+        let mut b = BitSet::with_capacity(100_000);
+        for i in 0..100 {
+            a.add(i);
+        }
+        b.add(5);
+        b.add(99_999);
+        let capacities = |set: &BitSet| [set.layer0.capacity(), set.layer1.capacity(), set.layer2.capacity()];
+        let (ca, cb) = (capacities(&a), capacities(&b));
+        let (pa, pb) = (a.layer0.as_ptr(), b.layer0.as_ptr());
+
+        a.swap_keep_capacity(&mut b);
+        assert_eq!((&a).iter().collect::<Vec<_>>(), [5, 99_999]);
+        assert!((&b).iter().eq(0..100));
+        assert_eq!((a.cached_len(), b.cached_len()), (2, 100));
+        assert_eq!((capacities(&a), capacities(&b)), (ca, cb));
+        assert_eq!((a.layer0.as_ptr(), b.layer0.as_ptr()), (pa, pb));
+
+        // the members of `b` fit within the allocation of `small`.
+        let mut small: BitSet = [1, 4_000].iter().collect();
+        let cs = capacities(&small);
+        small.swap_keep_capacity(&mut b);
+        assert!((&small).iter().eq(0..100));
+        assert_eq!((&b).iter().collect::<Vec<_>>(), [1, 4_000]);
+        assert_eq!((small.cached_len(), b.cached_len()), (100, 2));
+        assert_eq!((capacities(&small), capacities(&b)), (cs, cb));
+
+        // the members of `a` don't, so `small` grows to hold them.
+        small.swap_keep_capacity(&mut a);
+        assert_eq!((&small).iter().collect::<Vec<_>>(), [5, 99_999]);
+        assert!((&a).iter().eq(0..100));
+        assert_eq!(capacities(&a), ca);
+        assert!(small.layer0.capacity() > cs[0]);
+        a.add(99_998);
+        assert_eq!(a.layer0.capacity(), ca[0]);
+        assert!((&a).iter().eq((0..100).chain(Some(99_998))));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::{BitSet, BitSetAnd, BitSetNot, BitSetLike};
+    #[test]
+    fn split_words_mut() {
+        use std::thread;
+
+        let mut set: BitSet = [3, 100_000].iter().collect();
+        set.ensure_index(262_143);
+        let words = set.layer_lengths()[0];
+        assert_eq!(words, 4_096);
+
+        set.split_words_mut(&[1_000, 1_000, 2_500], |chunks| {
+            let ranges = chunks.iter().map(|chunk| chunk.word_range()).collect::<Vec<_>>();
+            assert_eq!(ranges, [0..1_000, 1_000..1_000, 1_000..2_500, 2_500..4_096]);
+            thread::scope(|scope| {
+                for mut chunk in chunks {
+                    scope.spawn(move || {
+                        for w in chunk.word_range().step_by(100) {
+                            chunk.or_word(w, 0b101);
+                        }
+                        if chunk.word_range().contains(&1_562) {
+                            // clears 100_000
+                            chunk.clear_word(1_562);
+                        }
+                    });
+                }
+            });
+        });
+
+        let expected = (0..4_096)
+            .filter(|w| w % 100 == 0)
+            .flat_map(|w| vec![w * 64, w * 64 + 2])
+            .chain(Some(3))
+            .collect::<BitSet>();
+        assert!((&set).iter().eq((&expected).iter()));
+        assert_eq!(set.cached_len(), expected.cached_len());
+        assert!(!set.contains(100_000));
+
+        set.split_words_mut(&[], |mut chunks| {
+            assert_eq!(chunks.len(), 1);
+            assert_eq!(chunks[0].words_mut().len(), words);
+        });
+    }
 
     #[test]
-    fn insert() {
-        let mut c = BitSet::new();
-        for i in 0..1_000 {
-            assert!(!c.add(i));
-            assert!(c.add(i));
+    #[should_panic(expected = "Expected ascending boundaries")]
+    fn split_words_mut_descending() {
+        let mut set = BitSet::with_capacity(10_000);
+        set.split_words_mut(&[100, 50], |_| {});
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected word index within 0..100")]
+    fn layer0_chunk_out_of_range() {
+        let mut set = BitSet::with_capacity(10_000);
+        set.split_words_mut(&[100], |mut chunks| chunks[0].or_word(100, 1));
+    }
+
+    #[test]
+    fn edit() {
+        let mut expected = BitSet::new();
+        for i in 0..200 {
+            expected.add(i * 37);
+        }
+        for i in 0..64 {
+            expected.remove(64 * 10 + i);
         }
+        expected.add(1_000_000);
+        expected.remove(0);
 
-        for i in 0..1_000 {
-            assert!(c.contains(i));
+        let mut set = BitSet::new();
+        set.edit(|editor| {
+            for i in 0..200 {
+                let id = i * 37;
+                editor.or_word(id >> ::BITS, 1 << (id & ((1 << ::BITS) - 1)));
+            }
+            editor.clear_word(10);
+            editor.clear_word(100_000);
+            editor.set_word(1_000_000 >> ::BITS, 1 << (1_000_000 & ((1 << ::BITS) - 1)));
+            editor.or_word(0, 0);
+        });
+        set.edit(|editor| editor.set_word(0, 1 << 37));
+
+        assert_eq!((&set).iter().collect::<Vec<_>>(), (&expected).iter().collect::<Vec<_>>());
+        assert_eq!(set.cached_len(), expected.cached_len());
+        assert_eq!(set.layer3, expected.layer3);
+        assert_eq!(set.layer2, expected.layer2);
+        assert_eq!(set.layer1, expected.layer1);
+    }
+
+    #[test]
+    fn approx_count() {
+        use rand::{Rng, weak_rng};
+
+        let mut rng = weak_rng();
+        let sets: Vec<BitSet> = vec![
+            (0..100_000).filter(|i| i % 3 == 0).collect(),
+            (500_000..510_000).chain(2_000_000..2_000_200).collect(),
+            (0..5_000).map(|_| rng.gen_range(0, 1 << 22)).collect(),
+            (0..50_000).map(|_| rng.gen_range(0, 1 << 18)).collect(),
+        ];
+        for set in &sets {
+            let (approx, exact) = (set.approx_count(), set.cached_len());
+            assert!(approx <= 2 * exact && exact <= 2 * approx, "{} vs {}", approx, exact);
         }
+        assert_eq!(BitSet::new().approx_count(), 0);
     }
 
     #[test]
-    fn insert_100k() {
-        let mut c = BitSet::new();
-        for i in 0..100_000 {
-            assert!(!c.add(i));
-            assert!(c.add(i));
+    fn partition() {
+        let set: BitSet = (0..1_000).collect();
+        let (evens, odds) = set.partition(|i| i % 2 == 0);
+        assert!((&evens).iter().eq((0..1_000).filter(|i| i % 2 == 0)));
+        assert!((&odds).iter().eq((0..1_000).filter(|i| i % 2 == 1)));
+        assert_eq!((evens.cached_len(), odds.cached_len()), (500, 500));
+        assert!((&evens | &odds).iter().eq((&set).iter()));
+
+        let (all, none) = set.partition(|_| true);
+        assert_eq!(all.cached_len(), 1_000);
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn transform_words() {
+        let mut set: BitSet = (0..10_000).chain(70_000..70_010).collect();
+        let every_other = !0 / 3;
+        set.transform_words(|_, word| word & every_other);
+        assert!((&set).iter().eq((0..10_000).chain(70_000..70_010).filter(|i| i % 2 == 0)));
+        assert_eq!(set.cached_len(), 5_005);
+
+        set.transform_words(|idx, word| if idx == 0 { word } else { 0 });
+        assert_eq!(set.cached_len(), 32);
+        assert!((&set).iter().eq((0..64).filter(|i| i % 2 == 0)));
+        assert_eq!(set.layer1[70_000 >> 12], 0);
+    }
+
+    #[test]
+    fn retain_top_k() {
+        let mut set: BitSet = (0..10).collect();
+        set.retain_top_k(3);
+        assert_eq!((&set).iter().collect::<Vec<_>>(), [7, 8, 9]);
+
+        let original: BitSet = (0..100_000).filter(|i| i % 3 == 0).chain(Some(9_000_000)).collect();
+        let all = (&original).iter().collect::<Vec<_>>();
+        for &k in &[0, 1, 2, 21, 22, 64, 65, 1_000, all.len() - 1, all.len(), all.len() + 5] {
+            let mut set = original.clone();
+            set.retain_top_k(k);
+            let start = all.len().saturating_sub(k);
+            assert_eq!((&set).iter().collect::<Vec<_>>(), &all[start..], "{}", k);
+            assert_eq!(set.cached_len(), all.len() - start);
         }
+    }
 
-        for i in 0..100_000 {
-            assert!(c.contains(i));
+    #[test]
+    fn retain_bottom_k() {
+        let mut set: BitSet = [2, 5, 9, 11, 20].iter().collect();
+        set.retain_bottom_k(3);
+        assert_eq!((&set).iter().collect::<Vec<_>>(), [2, 5, 9]);
+
+        let original: BitSet = (0..100_000).filter(|i| i % 3 == 0).chain(Some(9_000_000)).collect();
+        let all = (&original).iter().collect::<Vec<_>>();
+        for &k in &[0, 1, 2, 21, 22, 64, 65, 1_000, all.len() - 1, all.len(), all.len() + 5] {
+            let mut set = original.clone();
+            set.retain_bottom_k(k);
+            let end = ::std::cmp::min(k, all.len());
+            assert_eq!((&set).iter().collect::<Vec<_>>(), &all[..end], "{}", k);
+            assert_eq!(set.cached_len(), end);
         }
     }
+
     #[test]
-    fn remove() {
-        let mut c = BitSet::new();
-        for i in 0..1_000 {
-            assert!(!c.add(i));
+    fn remove_range_count() {
+        use count_in_range;
+
+        let original: BitSet = (0..100_000).filter(|i| i % 3 == 0).chain(Some(9_000_000)).collect();
+        let ranges = [0..0, 0..1, 5..6, 63..65, 100..5_000, 64..128, 99_000..16_777_216, 0..16_777_216, 200_000..300_000];
+        for range in ranges.iter().cloned() {
+            let mut set = original.clone();
+            let expected = count_in_range(&set, range.clone());
+            assert_eq!(set.remove_range_count(range.clone()), expected, "{:?}", range);
+            assert_eq!(set.cached_len(), original.cached_len() - expected);
+            assert!(!set.any_in_range(range.clone()));
+            assert_eq!(set.remove_range_count(range), 0);
         }
+    }
 
-        for i in 0..1_000 {
-            assert!(c.contains(i));
-            assert!(c.remove(i));
-            assert!(!c.contains(i));
-            assert!(!c.remove(i));
+    #[test]
+    fn truncate() {
+        use util::{Index, MAX_EID};
+
+        let word: Index = 1 << ::BITS;
+        let words = |max: Index| (max as usize + word as usize - 1) >> ::BITS;
+        let last = MAX_EID as Index - 1;
+        let original: BitSet = (0..1_000).filter(|i| i % 3 == 0).chain(Some(last)).collect();
+
+        let mut set = original.clone();
+        set.truncate(100);
+        assert!((&set).iter().eq((0..100).filter(|i| i % 3 == 0)));
+        assert_eq!(set.cached_len(), 34);
+        assert_eq!(set.layer_lengths(), [words(100), 1, 1, 1]);
+        assert_eq!(set.layer0.capacity(), words(100));
+        set.add(5_000);
+        assert!(set.contains(5_000) && set.contains(99));
+
+        for &max in &[0, 1, word, word + 1, 999, 1_000, word * word, last, last + 1] {
+            let mut set = original.clone();
+            set.truncate(max);
+            assert!((&set).iter().eq((&original).iter().filter(|&i| i < max)), "{}", max);
+            assert_eq!(set.cached_len(), (&set).iter().count());
+            assert!(set.layer0.len() <= words(max));
         }
     }
 
     #[test]
-    fn iter() {
-        let mut c = BitSet::new();
-        for i in 0..100_000 {
-            c.add(i);
+    fn union_shifted() {
+        let five: BitSet = [5].iter().collect();
+        let mut set = BitSet::new();
+        for &shift in &[-1, 0, 1] {
+            set.union_shifted(&five, shift);
         }
+        assert_eq!((&set).iter().collect::<Vec<_>>(), [4, 5, 6]);
+        assert_eq!(set.cached_len(), 3);
 
-        let mut count = 0;
-        for (idx, i) in c.iter().enumerate() {
-            count += 1;
-            assert_eq!(idx, i as usize);
+        let other: BitSet = (0..1_000).filter(|i| i % 7 == 0).chain(Some(300_000)).chain(Some(16_777_215)).collect();
+        let base: BitSet = (0..2_000).filter(|i| i % 5 == 0).collect();
+        for &shift in &[0, 1, -1, 63, 64, -64, 65, -65, 1_000, -1_000, 200_000, -300_000, 1 << 30, -(1 << 40)] {
+            let mut set = base.clone();
+            set.union_shifted(&other, shift);
+            let expected = (&base).iter()
+                .chain((&other).iter().map(|i| i as i64 + shift).filter(|i| (0..16_777_216).contains(i)).map(|i| i as u32))
+                .collect::<BitSet>();
+            assert!((&set).iter().eq((&expected).iter()), "{}", shift);
+            assert_eq!(set.cached_len(), expected.cached_len(), "{}", shift);
         }
-        assert_eq!(count, 100_000);
     }
 
     #[test]
-    fn iter_odd_even() {
-        let mut odd = BitSet::new();
-        let mut even = BitSet::new();
-        for i in 0..100_000 {
-            if i % 2 == 1 {
-                odd.add(i);
-            } else {
-                even.add(i);
-            }
+    fn dilate() {
+        let set: BitSet = [10, 20].iter().collect();
+        assert_eq!(set.dilate(1).iter().collect::<Vec<_>>(), [9, 10, 11, 19, 20, 21]);
+        assert_eq!(set.dilate(0).iter().collect::<Vec<_>>(), [10, 20]);
+        // overlapping neighbourhoods merge into a single run
+        assert!(set.dilate(5).iter().eq(5..26));
+        assert_eq!(set.dilate(5).cached_len(), 21);
+
+        let set: BitSet = [3, 1_000, 16_777_210].iter().collect();
+        for &radius in &[1, 2, 3, 7, 64, 100, 1_000] {
+            let dilated = set.dilate(radius);
+            let expected = (&set).iter()
+                .flat_map(|i| i.saturating_sub(radius)..::std::cmp::min(i + radius + 1, 16_777_216))
+                .collect::<BitSet>();
+            assert!((&dilated).iter().eq((&expected).iter()), "{}", radius);
+            assert_eq!(dilated.cached_len(), expected.cached_len());
         }
+        assert!(BitSet::new().dilate(10).is_empty());
+    }
 
-        assert_eq!((&odd).iter().count(), 50_000);
-        assert_eq!((&even).iter().count(), 50_000);
-        assert_eq!(BitSetAnd(&odd, &even).iter().count(), 0);
+    #[test]
+    fn erode() {
+        let run: BitSet = (0..100).collect();
+        assert!(run.erode(1).iter().eq(1..99));
+        assert!(run.erode(10).iter().eq(10..90));
+        assert!(run.erode(0).iter().eq(0..100));
+        assert!(run.erode(50).is_empty());
+
+        // isolated members and short runs are removed
+        let noisy: BitSet = (1_000..1_100).chain(vec![5, 500, 502, 503, 16_777_215]).collect();
+        assert!(noisy.erode(1).iter().eq(1_001..1_099));
+        assert!(noisy.erode(3).iter().eq(1_003..1_097));
+
+        let set: BitSet = (0..5_000).filter(|i| i % 200 < 150).collect();
+        for &radius in &[1, 2, 5, 63, 64, 74, 75] {
+            let expected = (&set).iter()
+                .filter(|&i| i >= radius && (i - radius..i + radius + 1).all(|j| set.contains(j)))
+                .collect::<BitSet>();
+            let eroded = set.erode(radius);
+            assert!((&eroded).iter().eq((&expected).iter()), "{}", radius);
+            assert_eq!(eroded.cached_len(), expected.cached_len());
+            assert!(eroded.dilate(radius).difference_is_empty(&set));
+        }
     }
 
     #[test]
-    fn iter_random_add() {
-        use rand::{Rng, weak_rng};
-        let mut set = BitSet::new();
-        let mut rng = weak_rng();
-        let limit = 1_048_576;
-        let mut added = 0;
-        for _ in 0..(limit / 10) {
-            let index = rng.gen_range(0, limit);
-            if !set.add(index) {
-                added += 1;
-            }
+    fn merge_disjoint() {
+        let original: BitSet = (0..100_000).filter(|i| i % 3 == 0).chain(Some(5_000_000)).collect();
+        for &split in &[0, 1, 30_000, 30_001, 99_999, 6_000_000] {
+            let mut low = original.clone_range(0..split);
+            let high = original.clone_range(split..16_777_216);
+            low.merge_disjoint(high);
+            assert!((&low).iter().eq((&original).iter()));
+            assert_eq!(low.cached_len(), original.cached_len());
+            assert_eq!(low.layer3, original.layer3);
+            assert_eq!(low.layer2, original.layer2);
+            assert_eq!(low.layer1, original.layer1);
         }
-        assert_eq!(set.iter().count(), added as usize);
+
+        let mut high = original.clone_range(50_000..16_777_216);
+        high.merge_disjoint(original.clone_range(0..50_000));
+        assert!((&high).iter().eq((&original).iter()));
     }
 
     #[test]
-    fn iter_clusters() {
-        let mut set = BitSet::new();
-        for x in 0..8 {
-            let x = (x * 3) << (::BITS * 2); // scale to the last slot
-            for y in 0..8 {
-                let y = (y * 3) << (::BITS);
-                for z in 0..8 {
-                    let z = z * 2;
-                    set.add(x + y + z);
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Expected sets to be disjoint")]
+    fn merge_overlapping() {
+        let mut set: BitSet = (0..10).collect();
+        set.merge_disjoint((5..15).collect());
+    }
+
+    #[test]
+    fn retain_intersection() {
+        use BitSetOr;
+
+        let a: BitSet = (0..5_000).filter(|i| i % 3 == 0).collect();
+        let b: BitSet = (4_000..300_000).filter(|i| i % 5 == 0).collect();
+        let mut target: BitSet = (0..200_000).filter(|i| i % 2 == 0).collect();
+        let expected: Vec<_> = BitSetAnd(&target, BitSetOr(&a, &b)).iter().collect();
+
+        target.retain_intersection(&BitSetOr(&a, &b));
+        assert_eq!((&target).iter().collect::<Vec<_>>(), expected);
+        assert_eq!(target.cached_len(), expected.len());
+
+        target.retain_intersection(&BitSet::new());
+        assert!(target.is_empty());
+        assert_eq!(target.cached_len(), 0);
+    }
+
+    #[test]
+    fn take() {
+        use Growth;
+
+        let mut set: BitSet = [3, 70, 400_000].iter().collect();
+        set.set_growth(Growth::Exact);
+        let capacity = set.layer0.capacity();
+
+        let taken = set.take();
+        assert_eq!((&taken).iter().collect::<Vec<_>>(), [3, 70, 400_000]);
+        assert_eq!(taken.layer0.capacity(), capacity);
+        assert!(set.is_empty());
+        assert_eq!(set.cached_len(), 0);
+        assert_eq!(set.layer0.capacity(), 0);
+        assert_eq!(set.growth, Growth::Exact);
+
+        set.add(5);
+        let taken = set.take_keep_capacity();
+        assert_eq!((&taken).iter().collect::<Vec<_>>(), [5]);
+        assert!(set.is_empty());
+        assert_eq!(set.cached_len(), 0);
+        assert!(set.layer0.capacity() > 0);
+        set.add(6);
+        assert_eq!((&set).iter().collect::<Vec<_>>(), [6]);
+    }
+
+    #[test]
+    fn rebuild_upper_layers() {
+        let expected: BitSet = [1, 5_000, 300_000, 9_000_000].iter().collect();
+        let mut set = expected.clone();
+        set.layer3 = 0b1010;
+        set.layer2[0] = !0;
+        for word in &mut set.layer1 {
+            *word = 0;
+        }
+        set.len = 17;
+
+        set.rebuild_upper_layers();
+        assert_eq!(set.layer3, expected.layer3);
+        assert_eq!(set.layer2, expected.layer2);
+        assert_eq!(set.layer1, expected.layer1);
+        assert_eq!(set.cached_len(), 4);
+        assert_eq!((&set).iter().collect::<Vec<_>>(), [1, 5_000, 300_000, 9_000_000]);
+    }
+
+    #[test]
+    fn growth() {
+        use Growth;
+
+        let reallocations = |growth| {
+            let mut set = BitSet::new();
+            set.set_growth(growth);
+            let (mut reallocations, mut capacity) = (0, 0);
+            for i in 0..200_000 {
+                set.add(i);
+                if set.layer0.capacity() != capacity {
+                    capacity = set.layer0.capacity();
+                    reallocations += 1;
                 }
             }
+            assert!((&set).iter().eq(0..200_000));
+            assert_eq!(set.cached_len(), 200_000);
+            (reallocations, set)
+        };
+
+        let (geometric, _) = reallocations(Growth::Geometric);
+        let (exact, set) = reallocations(Growth::Exact);
+        assert_eq!(exact, set.layer0.len());
+        assert_eq!(set.layer0.capacity(), set.layer0.len());
+        assert!(geometric * 100 < exact, "{} vs {}", geometric, exact);
+    }
+
+    #[test]
+    fn reserve_exact() {
+        let mut set = BitSet::new();
+        set.reserve_exact(100_000);
+        assert_eq!(set.layer_lengths(), [1_563, 25, 1, 1]);
+        assert_eq!(set.layer0.capacity(), 1_563);
+        assert_eq!(set.layer1.capacity(), 25);
+        assert_eq!(set.layer2.capacity(), 1);
+        assert!(set.is_empty());
+
+        set.add(5);
+        set.reserve_exact(200_000);
+        assert_eq!(set.layer0.capacity(), 3_126);
+        assert_eq!(set.layer1.capacity(), 49);
+        assert!((&set).iter().eq(Some(5)));
+
+        // already covered
+        set.reserve_exact(10);
+        assert_eq!(set.layer0.capacity(), 3_126);
+        for i in 0..200_000 {
+            set.add(i);
         }
-        assert_eq!(set.iter().count(), 8usize.pow(3));
+        assert_eq!(set.layer0.capacity(), 3_126);
     }
 
     #[test]
-    fn not() {
-        let mut c = BitSet::new();
-        for i in 0..10_000 {
-            if i % 2 == 1 {
-                c.add(i);
-            }
+    fn contains_checked() {
+        use CapacityError;
+
+        let set: BitSet = [3, 70_000].iter().collect();
+        assert_eq!(set.contains_checked(3), Ok(true));
+        assert_eq!(set.contains_checked(4), Ok(false));
+        // absent, and beyond the allocated words
+        assert_eq!(set.contains_checked(16_777_215), Ok(false));
+
+        for &id in &[16_777_216, 20_000_000, u32::MAX] {
+            let error = set.contains_checked(id).unwrap_err();
+            assert_eq!(error, CapacityError { index: id });
+            assert_eq!(error.index(), id);
         }
-        let d = BitSetNot(c);
-        for (idx, i) in d.iter().take(5_000).enumerate() {
-            assert_eq!(idx * 2, i as usize);
+        assert_eq!(
+            set.contains_checked(16_777_216).unwrap_err().to_string(),
+            "index 16777216 is out of range, indices must be less than 16777216"
+        );
+    }
+
+    #[test]
+    fn ensure_index() {
+        let mut set: BitSet = [100].iter().collect();
+        assert!(set.contains_capacity(127));
+        assert!(!set.contains_capacity(128));
+
+        set.ensure_index(5_000);
+        assert!(set.contains_capacity(5_000));
+        assert!(set.contains_capacity(5_055));
+        assert!(!set.contains_capacity(5_056));
+        assert_eq!(set.layer_lengths(), [5_000 / 64 + 1, 2, 1, 1]);
+        assert_eq!((&set).iter().collect::<Vec<_>>(), [100]);
+
+        set.ensure_index(10);
+        assert_eq!(set.layer_lengths()[0], 5_000 / 64 + 1);
+    }
+
+    #[test]
+    fn reset_to_capacity() {
+        let mut set: BitSet = (0..1_000).chain(Some(300_000)).collect();
+        set.reset_to_capacity(100_000);
+        assert!(set.is_empty());
+        assert_eq!(set.cached_len(), 0);
+        assert_eq!((&set).iter().count(), 0);
+        assert!(set.contains_capacity(300_000));
+
+        let mut set = BitSet::new();
+        set.reset_to_capacity(100_000);
+        let pointers = (set.layer0.as_ptr(), set.layer1.as_ptr(), set.layer2.as_ptr());
+        for i in 0..100_001 {
+            set.add(i);
+        }
+        assert_eq!((set.layer0.as_ptr(), set.layer1.as_ptr(), set.layer2.as_ptr()), pointers);
+        assert_eq!(set.cached_len(), 100_001);
+
+        set.reset_to_capacity(10);
+        assert!(set.is_empty());
+        assert!(set.contains_capacity(100_000));
+    }
+
+    #[test]
+    fn single_element() {
+        let mut set = BitSet::new();
+        assert!(!set.is_single());
+        assert_eq!(set.single_element(), None);
+
+        set.add(1_000_003);
+        assert!(set.is_single());
+        assert_eq!(set.single_element(), Some(1_000_003));
+
+        set.add(2);
+        assert!(!set.is_single());
+        assert_eq!(set.single_element(), None);
+
+        set.remove(1_000_003);
+        assert_eq!(set.single_element(), Some(2));
+    }
+
+    #[test]
+    fn set_word() {
+        let mut set = BitSet::new();
+        set.set_word(2, 0b101);
+        let base = 2 << ::BITS;
+        assert_eq!((&set).iter().collect::<Vec<_>>(), [base, base + 2]);
+
+        set.set_word(2, 0b110);
+        set.set_word(5_000, 1 << 3);
+        set.set_word(7, 0);
+        let expected: BitSet = [base, base + 1, base + 2, (5_000 << ::BITS) + 3].iter().collect();
+        assert_eq!(set.cached_len(), 4);
+        assert_eq!(set.layer3, expected.layer3);
+        assert_eq!(set.layer2, expected.layer2);
+        assert_eq!(set.layer1, expected.layer1);
+        assert_eq!(set.layer0, expected.layer0);
+    }
+
+    #[test]
+    fn boxed_dyn() {
+        use {AtomicBitSet, BitSetOr};
+
+        let mut a = BitSet::new();
+        let mut b = AtomicBitSet::new();
+        for i in 0..1_000 {
+            if i % 3 == 0 {
+                a.add(i);
+            }
+            if i % 5 == 0 {
+                b.add(i);
+            }
         }
+
+        let sets: Vec<Box<dyn BitSetLike>> = vec![
+            Box::new(a.clone()),
+            Box::new(b),
+            Box::new(BitSetAnd(a.clone(), BitSetNot(a.clone()))),
+            Box::new(BitSetOr(a, BitSet::new())),
+        ];
+        let counts = sets.iter().map(|set| set.iter().count()).collect::<Vec<_>>();
+        assert_eq!(counts, vec![334, 200, 0, 334]);
+
+        let firsts = sets.into_iter().map(|set| set.iter().next()).collect::<Vec<_>>();
+        assert_eq!(firsts, vec![Some(0), Some(0), None, Some(0)]);
     }
 }
 
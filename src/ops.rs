@@ -1,11 +1,11 @@
 
 use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not};
 use std::iter::{FromIterator, IntoIterator};
-use std::usize;
+use std::{cmp, fmt, usize};
 
 use util::*;
 
-use {AtomicBitSet, BitIter, BitSet, BitSetLike, DrainableBitSet};
+use {count_in_range, is_empty_by_descent, AtomicBitSet, BitIter, BitSet, BitSetLike, DrainableBitSet};
 
 impl<'a, B> BitOrAssign<&'a B> for BitSet
     where B: BitSetLike
@@ -16,7 +16,11 @@ impl<'a, B> BitOrAssign<&'a B> for BitSet
         while let Some(level) = (1..LAYERS).find(|&level| iter.handle_level(level) == Continue) {
             let lower = level - 1;
             let idx = iter.prefix[lower] as usize >> BITS;
-            *self.layer_mut(lower, idx) |= lhs.get_from_layer(lower, idx);
+            let their_layer = lhs.get_from_layer(lower, idx);
+            if lower == 0 {
+                self.len += (their_layer & !self.layer0(idx)).count_ones() as usize;
+            }
+            *self.layer_mut(lower, idx) |= their_layer;
         }
         self.layer3 |= lhs.layer3();
     }
@@ -41,6 +45,9 @@ impl<'a, B> BitAndAssign<&'a B> for BitSet
             masks[lower] = our_layer & !their_layer;
             BitIter::new(&mut *self, masks, iter.prefix).clear();
 
+            if lower == 0 {
+                self.len -= (our_layer & !their_layer).count_ones() as usize;
+            }
             *self.layer_mut(lower, idx) &= their_layer;
         }
         let mut masks = [0; LAYERS];
@@ -62,7 +69,9 @@ impl<'a, B> BitXorAssign<&'a B> for BitSet
             let idx = iter.prefix[lower] as usize >> BITS;
 
             if lower == 0 {
+                let old = self.layer0(idx).count_ones() as usize;
                 *self.layer_mut(lower, idx) ^= lhs.get_from_layer(lower, idx);
+                self.len = self.len - old + self.layer0(idx).count_ones() as usize;
 
                 let mut change_bit = |level| {
                     let lower = level - 1;
@@ -94,6 +103,21 @@ impl<'a, B> BitXorAssign<&'a B> for BitSet
 #[derive(Debug)]
 pub struct BitSetAnd<A: BitSetLike, B: BitSetLike>(pub A, pub B);
 
+impl<A: BitSetLike, B: BitSetLike> BitSetAnd<A, B> {
+    /// Combines the words of both operands, skipping the word of the second
+    /// operand when the first one is already empty.
+    #[inline]
+    fn combine<F, G>(&self, a: F, b: G) -> usize
+        where F: FnOnce(&A) -> usize,
+              G: FnOnce(&B) -> usize
+    {
+        match a(&self.0) {
+            0 => 0,
+            word => word & b(&self.1),
+        }
+    }
+}
+
 impl<A: BitSetLike, B: BitSetLike> BitSetLike for BitSetAnd<A, B> {
     #[inline]
     fn layer3(&self) -> usize {
@@ -101,20 +125,26 @@ impl<A: BitSetLike, B: BitSetLike> BitSetLike for BitSetAnd<A, B> {
     }
     #[inline]
     fn layer2(&self, i: usize) -> usize {
-        self.0.layer2(i) & self.1.layer2(i)
+        self.combine(|a| a.layer2(i), |b| b.layer2(i))
     }
     #[inline]
     fn layer1(&self, i: usize) -> usize {
-        self.0.layer1(i) & self.1.layer1(i)
+        self.combine(|a| a.layer1(i), |b| b.layer1(i))
     }
     #[inline]
     fn layer0(&self, i: usize) -> usize {
-        self.0.layer0(i) & self.1.layer0(i)
+        self.combine(|a| a.layer0(i), |b| b.layer0(i))
     }
     #[inline]
     fn contains(&self, i: Index) -> bool {
         self.0.contains(i) && self.1.contains(i)
     }
+    /// Disjoint top layers are enough to rule out any shared index,
+    /// otherwise the intersection has to be descended.
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.layer3() == 0 || is_empty_by_descent(self)
+    }
 }
 
 impl<A: DrainableBitSet, B: DrainableBitSet> DrainableBitSet for BitSetAnd<A, B> {
@@ -159,6 +189,10 @@ impl<A: BitSetLike, B: BitSetLike> BitSetLike for BitSetOr<A, B> {
     fn contains(&self, i: Index) -> bool {
         self.0.contains(i) || self.1.contains(i)
     }
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.0.is_empty() && self.1.is_empty()
+    }
 }
 
 impl<A: DrainableBitSet, B: DrainableBitSet> DrainableBitSet for BitSetOr<A, B> {
@@ -174,6 +208,129 @@ impl<A: DrainableBitSet, B: DrainableBitSet> DrainableBitSet for BitSetOr<A, B>
     }
 }
 
+/// `BitSetAnd3` takes three [`BitSetLike`] items, and merges the masks
+/// returning a new virtual set, which represents an intersection of the
+/// three original sets.
+///
+/// This is the same set as `BitSetAnd(BitSetAnd(a, b), c)`, but every word
+/// is combined in one place instead of through nested intersections.
+///
+/// [`BitSetLike`]: ../trait.BitSetLike.html
+#[derive(Debug)]
+pub struct BitSetAnd3<A: BitSetLike, B: BitSetLike, C: BitSetLike>(pub A, pub B, pub C);
+
+impl<A: BitSetLike, B: BitSetLike, C: BitSetLike> BitSetAnd3<A, B, C> {
+    /// Combines the words of all operands, skipping the words of the later
+    /// operands as soon as the intersection is empty.
+    #[inline]
+    fn combine<F, G, H>(&self, a: F, b: G, c: H) -> usize
+        where F: FnOnce(&A) -> usize,
+              G: FnOnce(&B) -> usize,
+              H: FnOnce(&C) -> usize
+    {
+        match a(&self.0) {
+            0 => 0,
+            word => match word & b(&self.1) {
+                0 => 0,
+                word => word & c(&self.2),
+            },
+        }
+    }
+}
+
+impl<A: BitSetLike, B: BitSetLike, C: BitSetLike> BitSetLike for BitSetAnd3<A, B, C> {
+    #[inline]
+    fn layer3(&self) -> usize {
+        self.0.layer3() & self.1.layer3() & self.2.layer3()
+    }
+    #[inline]
+    fn layer2(&self, i: usize) -> usize {
+        self.combine(|a| a.layer2(i), |b| b.layer2(i), |c| c.layer2(i))
+    }
+    #[inline]
+    fn layer1(&self, i: usize) -> usize {
+        self.combine(|a| a.layer1(i), |b| b.layer1(i), |c| c.layer1(i))
+    }
+    #[inline]
+    fn layer0(&self, i: usize) -> usize {
+        self.combine(|a| a.layer0(i), |b| b.layer0(i), |c| c.layer0(i))
+    }
+    #[inline]
+    fn contains(&self, i: Index) -> bool {
+        self.0.contains(i) && self.1.contains(i) && self.2.contains(i)
+    }
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.layer3() == 0 || is_empty_by_descent(self)
+    }
+}
+
+impl<A: DrainableBitSet, B: DrainableBitSet, C: DrainableBitSet> DrainableBitSet for BitSetAnd3<A, B, C> {
+    #[inline]
+    fn remove(&mut self, i: Index) -> bool {
+        if self.contains(i) {
+            self.0.remove(i);
+            self.1.remove(i);
+            self.2.remove(i);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// `BitSetOr3` takes three [`BitSetLike`] items, and merges the masks
+/// returning a new virtual set, which represents an merged of the
+/// three original sets.
+///
+/// This is the same set as `BitSetOr(BitSetOr(a, b), c)`, but every word
+/// is combined in one place instead of through nested unions.
+///
+/// [`BitSetLike`]: ../trait.BitSetLike.html
+#[derive(Debug)]
+pub struct BitSetOr3<A: BitSetLike, B: BitSetLike, C: BitSetLike>(pub A, pub B, pub C);
+
+impl<A: BitSetLike, B: BitSetLike, C: BitSetLike> BitSetLike for BitSetOr3<A, B, C> {
+    #[inline]
+    fn layer3(&self) -> usize {
+        self.0.layer3() | self.1.layer3() | self.2.layer3()
+    }
+    #[inline]
+    fn layer2(&self, i: usize) -> usize {
+        self.0.layer2(i) | self.1.layer2(i) | self.2.layer2(i)
+    }
+    #[inline]
+    fn layer1(&self, i: usize) -> usize {
+        self.0.layer1(i) | self.1.layer1(i) | self.2.layer1(i)
+    }
+    #[inline]
+    fn layer0(&self, i: usize) -> usize {
+        self.0.layer0(i) | self.1.layer0(i) | self.2.layer0(i)
+    }
+    #[inline]
+    fn contains(&self, i: Index) -> bool {
+        self.0.contains(i) || self.1.contains(i) || self.2.contains(i)
+    }
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.0.is_empty() && self.1.is_empty() && self.2.is_empty()
+    }
+}
+
+impl<A: DrainableBitSet, B: DrainableBitSet, C: DrainableBitSet> DrainableBitSet for BitSetOr3<A, B, C> {
+    #[inline]
+    fn remove(&mut self, i: Index) -> bool {
+        if self.contains(i) {
+            self.0.remove(i);
+            self.1.remove(i);
+            self.2.remove(i);
+            true
+        } else {
+            false
+        }
+    }
+}
+
 /// `BitSetNot` takes a [`BitSetLike`] item, and produced an inverted virtual set.
 /// Note: the implementation is sub-optimal because layers 1-3 are not active.
 ///
@@ -202,6 +359,128 @@ impl<A: BitSetLike> BitSetLike for BitSetNot<A> {
     fn contains(&self, i: Index) -> bool {
         !self.0.contains(i)
     }
+    /// Counts the complement within all the indices a bit set can hold.
+    #[inline]
+    fn count(&self) -> usize {
+        MAX_EID - self.0.count()
+    }
+}
+
+/// `BitSetNotBounded` takes a [`BitSetLike`] item and a bound, and produces
+/// an inverted virtual set which only holds the indices below the bound.
+///
+/// Unlike [`BitSetNot`] the upper layers are active, and only cover the
+/// indices below the bound.
+///
+/// [`BitSetLike`]: ../trait.BitSetLike.html
+/// [`BitSetNot`]: struct.BitSetNot.html
+#[derive(Debug)]
+pub struct BitSetNotBounded<A: BitSetLike>(pub A, pub Index);
+
+impl<A: BitSetLike> BitSetNotBounded<A> {
+    #[inline]
+    fn mask(&self, level: usize, idx: usize) -> usize {
+        bounded_mask(self.1, level, idx)
+    }
+}
+
+/// Returns the word of `level` at `idx` which has a bit set for each
+/// word below it that covers an index under `bound`.
+#[inline]
+fn bounded_mask(bound: Index, level: usize, idx: usize) -> usize {
+    let shift = BITS * level;
+    let bound = cmp::min(bound as usize, MAX_EID);
+    let units = (bound + (1 << shift) - 1) >> shift;
+    let first = idx << BITS;
+    if units <= first {
+        0
+    } else if units - first >= 1 << BITS {
+        !0
+    } else {
+        (1 << (units - first)) - 1
+    }
+}
+
+impl<A: BitSetLike> BitSetLike for BitSetNotBounded<A> {
+    #[inline]
+    fn layer3(&self) -> usize {
+        self.mask(3, 0)
+    }
+    #[inline]
+    fn layer2(&self, i: usize) -> usize {
+        self.mask(2, i)
+    }
+    #[inline]
+    fn layer1(&self, i: usize) -> usize {
+        self.mask(1, i)
+    }
+    #[inline]
+    fn layer0(&self, i: usize) -> usize {
+        !self.0.layer0(i) & self.mask(0, i)
+    }
+    #[inline]
+    fn contains(&self, i: Index) -> bool {
+        (i as usize) < cmp::min(self.1 as usize, MAX_EID) && !self.0.contains(i)
+    }
+    #[inline]
+    fn count(&self) -> usize {
+        cmp::min(self.1 as usize, MAX_EID) - count_in_range(&self.0, 0..self.1)
+    }
+}
+
+/// `FnBitSet` takes a predicate and a bound, and produces a virtual set
+/// which holds the indices below the bound for which the predicate
+/// returns `true`.
+///
+/// This is slow: the upper layers cover every index below the bound, and
+/// each word of layer 0 is computed by calling the predicate once per bit.
+/// Iterating over it on its own calls the predicate for every index below
+/// the bound, so it is best used as a mask in combination with other sets,
+/// for example in a [`BitSetAnd`], where it is only consulted for the
+/// words the other set has members in.
+///
+/// [`BitSetAnd`]: struct.BitSetAnd.html
+pub struct FnBitSet<F: Fn(Index) -> bool>(pub F, pub Index);
+
+// Closures don't implement `Debug`, so only the bound is printed.
+impl<F: Fn(Index) -> bool> fmt::Debug for FnBitSet<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("FnBitSet")
+            .field(&format_args!(".."))
+            .field(&self.1)
+            .finish()
+    }
+}
+
+impl<F: Fn(Index) -> bool> BitSetLike for FnBitSet<F> {
+    #[inline]
+    fn layer3(&self) -> usize {
+        bounded_mask(self.1, 3, 0)
+    }
+    #[inline]
+    fn layer2(&self, i: usize) -> usize {
+        bounded_mask(self.1, 2, i)
+    }
+    #[inline]
+    fn layer1(&self, i: usize) -> usize {
+        bounded_mask(self.1, 1, i)
+    }
+    fn layer0(&self, i: usize) -> usize {
+        let mut word = 0;
+        let mut mask = bounded_mask(self.1, 0, i);
+        while mask != 0 {
+            let bit = mask.trailing_zeros() as usize;
+            mask &= !(1 << bit);
+            if (self.0)(((i << BITS) | bit) as Index) {
+                word |= 1 << bit;
+            }
+        }
+        word
+    }
+    #[inline]
+    fn contains(&self, i: Index) -> bool {
+        (i as usize) < cmp::min(self.1 as usize, MAX_EID) && (self.0)(i)
+    }
 }
 
 /// `BitSetXor` takes two [`BitSetLike`] items, and merges the masks
@@ -326,10 +605,16 @@ operator!(impl<()()> for AtomicBitSet);
 operator!(impl<('a)()> for &'a AtomicBitSet);
 operator!(impl<()(A)> for BitSetNot<A>);
 operator!(impl<('a)(A)> for &'a BitSetNot<A>);
+operator!(impl<()(A)> for BitSetNotBounded<A>);
+operator!(impl<('a)(A)> for &'a BitSetNotBounded<A>);
 operator!(impl<()(A, B)> for BitSetAnd<A, B>);
 operator!(impl<('a)(A, B)> for &'a BitSetAnd<A, B>);
 operator!(impl<()(A, B)> for BitSetOr<A, B>);
 operator!(impl<('a)(A, B)> for &'a BitSetOr<A, B>);
+operator!(impl<()(A, B, C)> for BitSetAnd3<A, B, C>);
+operator!(impl<('a)(A, B, C)> for &'a BitSetAnd3<A, B, C>);
+operator!(impl<()(A, B, C)> for BitSetOr3<A, B, C>);
+operator!(impl<('a)(A, B, C)> for &'a BitSetOr3<A, B, C>);
 operator!(impl<()(A, B)> for BitSetXor<A, B>);
 operator!(impl<('a)(A, B)> for &'a BitSetXor<A, B>);
 operator!(impl<()()> for BitSetAll);
@@ -685,6 +970,126 @@ mod tests {
         }
     }
 
+    #[test]
+    fn not_count() {
+        use {BitSetAll, BitSetAnd, BitSetNot, BitSetNotBounded};
+        use util::MAX_EID;
+
+        let set: BitSet = (0..30).map(|i| i * 3).collect();
+        assert_eq!(set.count(), 30);
+
+        let bounded = BitSetNotBounded(&set, 100);
+        assert_eq!(bounded.count(), 70);
+        assert_eq!((&bounded).iter().count(), 70);
+        assert!((&bounded).iter().eq((0..100).filter(|&i| !set.contains(i))));
+        assert_eq!(BitSetNotBounded(&set, 50).count(), (&BitSetNotBounded(&set, 50)).iter().count());
+        assert_eq!(BitSetNotBounded(&set, 0).count(), 0);
+        assert_eq!(
+            BitSetNotBounded(&set, 100_000).count(),
+            (&BitSetNotBounded(&set, 100_000)).iter().count()
+        );
+
+        let not = BitSetNot(&set);
+        assert_eq!(not.count(), MAX_EID - 30);
+        // the default count descends through the hierarchy instead
+        assert_eq!(BitSetAnd(&not, BitSetAll).count(), MAX_EID - 30);
+    }
+
+    #[test]
+    fn three_way() {
+        use {BitSetAnd, BitSetAnd3, BitSetOr, BitSetOr3};
+
+        let a: BitSet = (0..100_000).filter(|i| i % 2 == 0).collect();
+        let b: BitSet = (50_000..200_000).filter(|i| i % 3 == 0).collect();
+        let c: BitSet = (0..300_000).filter(|i| i % 5 == 0).chain(1_000_000..1_000_100).collect();
+
+        let and = BitSetAnd3(&a, &b, &c);
+        assert!((&and).iter().eq(BitSetAnd(BitSetAnd(&a, &b), &c).iter()));
+        assert!((&and).iter().eq((50_000..100_000).filter(|i| i % 30 == 0)));
+        assert!(!and.is_empty());
+        assert!(and.contains(60_000) && !and.contains(60_010));
+
+        let or = BitSetOr3(&a, &b, &c);
+        assert!((&or).iter().eq(BitSetOr(BitSetOr(&a, &b), &c).iter()));
+        assert!(or.contains(1_000_050) && !or.contains(199_999));
+        assert_eq!((&or).iter().count(), (0..300_000).filter(|&i| {
+            (i < 100_000 && i % 2 == 0) || ((50_000..200_000).contains(&i) && i % 3 == 0) || i % 5 == 0
+        }).count() + 100);
+
+        let d: BitSet = (300_000..400_000).collect();
+        assert!(BitSetAnd3(&a, &b, &d).is_empty());
+        assert!(BitSetOr3(BitSet::new(), BitSet::new(), BitSet::new()).is_empty());
+        assert!((BitSetAnd3(&a, &b, &c) | &d).iter().eq(BitSetOr(&and, &d).iter()));
+    }
+
+    #[test]
+    fn and_loads() {
+        use std::cell::Cell;
+        use BitSetAnd;
+
+        struct Counting<T> {
+            set: T,
+            loads: Cell<usize>,
+        }
+
+        impl<T: BitSetLike> BitSetLike for Counting<T> {
+            fn layer3(&self) -> usize {
+                self.set.layer3()
+            }
+            fn layer2(&self, i: usize) -> usize {
+                self.loads.set(self.loads.get() + 1);
+                self.set.layer2(i)
+            }
+            fn layer1(&self, i: usize) -> usize {
+                self.loads.set(self.loads.get() + 1);
+                self.set.layer1(i)
+            }
+            fn layer0(&self, i: usize) -> usize {
+                self.loads.set(self.loads.get() + 1);
+                self.set.layer0(i)
+            }
+            fn contains(&self, i: Index) -> bool {
+                self.set.contains(i)
+            }
+        }
+
+        let x: BitSet = (0..100_000).filter(|i| i % 64 < 32).collect();
+        let y: BitSet = (0..100_000).filter(|i| i % 64 >= 32).collect();
+        let z: BitSet = (0..100_000).filter(|i| i % 5 == 0).collect();
+        let words = 1 + 25 + 1_563;
+
+        // the words of `z` are needed wherever `x & z` has members.
+        let a = Counting { set: BitSetAnd(&x, &z), loads: Cell::new(0) };
+        let b = Counting { set: &z, loads: Cell::new(0) };
+        let and = BitSetAnd(&a, &b);
+        assert!((&and).iter().eq((0..100_000).filter(|i| i % 5 == 0 && i % 64 < 32)));
+        assert_eq!((a.loads.get(), b.loads.get()), (words, words));
+
+        // the upper layers of `x & y` claim every word, but their members
+        // never overlap, so the words of `z` in layer 0 are never needed.
+        let a = Counting { set: BitSetAnd(&x, &y), loads: Cell::new(0) };
+        let b = Counting { set: &z, loads: Cell::new(0) };
+        let and = BitSetAnd(&a, &b);
+        assert_eq!((&and).iter().count(), 0);
+        // `y` has no members in the last word of layer 0
+        assert_eq!((a.loads.get(), b.loads.get()), (words - 1, 1 + 25));
+    }
+
+    #[test]
+    fn fn_bitset() {
+        use {BitSetAnd, FnBitSet};
+
+        let set: BitSet = (0..2_000).filter(|i| i % 2 == 0).collect();
+        let thirds = BitSetAnd(&set, FnBitSet(|i| i % 3 == 0, 1000));
+        assert!(thirds.iter().eq((0..1000).filter(|i| i % 6 == 0)));
+
+        let mask = FnBitSet(|i| i % 5 == 1, 130);
+        assert!(mask.contains(126));
+        assert!(!mask.contains(131));
+        assert_eq!(format!("{:?}", mask), "FnBitSet(.., 130)");
+        assert!(mask.iter().eq((0..130).filter(|i| i % 5 == 1)));
+    }
+
     #[test]
     fn xor() {
         // 0011
@@ -0,0 +1,70 @@
+use roaring::RoaringBitmap;
+
+use {BitSet, Ranges};
+
+impl BitSet {
+    /// Converts the set into a `RoaringBitmap` with the same members.
+    ///
+    /// Runs of consecutive indices are inserted into the bitmap as whole
+    /// ranges.
+    pub fn to_roaring(&self) -> RoaringBitmap {
+        let mut bitmap = RoaringBitmap::new();
+        for range in Ranges::new(self) {
+            bitmap.insert_range(range);
+        }
+        bitmap
+    }
+
+    /// Creates a `BitSet` with the same members as `bitmap`.
+    ///
+    /// Panics if `bitmap` holds an index which a `BitSet` can't represent.
+    pub fn from_roaring(bitmap: &RoaringBitmap) -> BitSet {
+        match bitmap.max() {
+            Some(max) => {
+                let mut set = BitSet::with_capacity(max);
+                for id in bitmap {
+                    set.add(id);
+                }
+                set
+            }
+            None => BitSet::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, weak_rng};
+    use roaring::RoaringBitmap;
+    use {BitSet, BitSetLike};
+
+    #[test]
+    fn round_trip() {
+        let mut rng = weak_rng();
+        let sets: Vec<BitSet> = vec![
+            BitSet::new(),
+            (0..1_000).map(|_| rng.gen_range(0, 16_777_216)).collect(),
+            (0..500_000).filter(|i| i % 7 != 0).chain(16_777_000..16_777_216).collect(),
+        ];
+        for set in &sets {
+            let bitmap = set.to_roaring();
+            assert_eq!(bitmap.len() as usize, set.cached_len());
+            assert!(set.iter().eq(bitmap.iter()));
+
+            let back = BitSet::from_roaring(&bitmap);
+            assert!((&back).iter().eq(set.iter()));
+        }
+
+        let bitmap: RoaringBitmap = (100..200).chain(Some(16_777_215)).collect();
+        let set = BitSet::from_roaring(&bitmap);
+        assert!(bitmap.iter().all(|i| set.contains(i)));
+        assert_eq!(set.to_roaring(), bitmap);
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected index to be less then")]
+    fn out_of_range() {
+        let bitmap: RoaringBitmap = Some(1 << 30).into_iter().collect();
+        BitSet::from_roaring(&bitmap);
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::btree_map::{self, BTreeMap};
+use std::iter::FromIterator;
+
+use util::*;
+use {BitIter, BitSet, BitSetLike};
+
+/// A set of `u64` indices, for keyspaces which don't fit in a [`BitSet`].
+///
+/// The keyspace is split into chunks of `usize**4` indices, the capacity
+/// of a single `BitSet`, and each chunk with members in it is stored as a
+/// `BitSet` of its own. Operations within a chunk are as fast as on a
+/// `BitSet`, with an additional map lookup to find the chunk.
+///
+/// Unlike a `BitSet` this is not a `BitSetLike`, since the layers of a
+/// `BitSetLike` can only address `u32` indices.
+///
+/// [`BitSet`]: struct.BitSet.html
+#[derive(Clone, Debug, Default)]
+pub struct BitSet64 {
+    chunks: BTreeMap<u64, BitSet>,
+}
+
+fn split(id: u64) -> (u64, Index) {
+    (id / MAX_EID as u64, (id % MAX_EID as u64) as Index)
+}
+
+impl BitSet64 {
+    /// Creates an empty `BitSet64`.
+    pub fn new() -> BitSet64 {
+        Default::default()
+    }
+
+    /// Adds `id` to the set. Returns `true` if the value was already in
+    /// the set.
+    pub fn add(&mut self, id: u64) -> bool {
+        let (chunk, id) = split(id);
+        self.chunks.entry(chunk).or_default().add(id)
+    }
+
+    /// Removes `id` from the set, returns `true` if the value was removed,
+    /// and `false` if the value was not set to begin with.
+    pub fn remove(&mut self, id: u64) -> bool {
+        let (chunk, id) = split(id);
+        let (removed, empty) = match self.chunks.get_mut(&chunk) {
+            Some(set) => (set.remove(id), set.is_empty()),
+            None => return false,
+        };
+        if empty {
+            self.chunks.remove(&chunk);
+        }
+        removed
+    }
+
+    /// Returns `true` if `id` is in the set.
+    pub fn contains(&self, id: u64) -> bool {
+        let (chunk, id) = split(id);
+        match self.chunks.get(&chunk) {
+            Some(set) => set.contains(id),
+            None => false,
+        }
+    }
+
+    /// Returns the number of indices in the set.
+    pub fn len(&self) -> usize {
+        self.chunks.values().map(BitSet::cached_len).sum()
+    }
+
+    /// Returns `true` if the set doesn't contain any index.
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Completely wipes out the set.
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+    }
+
+    /// Creates an iterator over the indices of the set in ascending order.
+    pub fn iter(&self) -> BitSet64Iter<'_> {
+        BitSet64Iter {
+            chunks: self.chunks.iter(),
+            current: None,
+        }
+    }
+}
+
+/// An `Iterator` over the indices of a [`BitSet64`] in ascending order.
+///
+/// [`BitSet64`]: struct.BitSet64.html
+#[derive(Debug)]
+pub struct BitSet64Iter<'a> {
+    chunks: btree_map::Iter<'a, u64, BitSet>,
+    current: Option<(u64, BitIter<&'a BitSet>)>,
+}
+
+impl<'a> Iterator for BitSet64Iter<'a> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((base, ref mut iter)) = self.current {
+                if let Some(id) = iter.next() {
+                    return Some(base + u64::from(id));
+                }
+            }
+            let (&chunk, set) = self.chunks.next()?;
+            self.current = Some((chunk * MAX_EID as u64, set.iter()));
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a BitSet64 {
+    type Item = u64;
+    type IntoIter = BitSet64Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl FromIterator<u64> for BitSet64 {
+    fn from_iter<T>(iter: T) -> Self
+    where
+        T: IntoIterator<Item = u64>,
+    {
+        let mut set = BitSet64::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl Extend<u64> for BitSet64 {
+    fn extend<T>(&mut self, iter: T)
+    where
+        T: IntoIterator<Item = u64>,
+    {
+        for id in iter {
+            self.add(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BitSet64;
+
+    #[test]
+    fn above_u32() {
+        let big = u64::from(u32::MAX);
+        let ids = [0, 5, 16_777_215, 16_777_216, big, big + 1, big * 1_000, u64::MAX];
+        let mut set = BitSet64::new();
+        for &id in ids.iter().rev() {
+            assert!(!set.add(id));
+        }
+        assert!(set.add(big + 1));
+        assert_eq!(set.len(), ids.len());
+        assert!(set.contains(big * 1_000));
+        assert!(!set.contains(big * 1_000 + 1));
+        assert_eq!(set.iter().collect::<Vec<_>>(), ids);
+
+        assert!(set.remove(big));
+        assert!(!set.remove(big));
+        assert!(!set.remove(big + 2));
+        assert!(!set.contains(big));
+        assert_eq!(set.len(), ids.len() - 1);
+
+        let collected: BitSet64 = ids.iter().cloned().collect();
+        assert_eq!((&collected).into_iter().count(), ids.len());
+        set.clear();
+        assert!(set.is_empty());
+        assert_eq!(set.iter().next(), None);
+    }
+}
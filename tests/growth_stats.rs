@@ -0,0 +1,27 @@
+//! The growth count is shared by every set in the process, so it is only
+//! checked from this binary, where no other test grows sets meanwhile.
+#![cfg(feature = "growth-stats")]
+
+extern crate hibitset;
+
+use hibitset::{growth_events, BitSet, Growth};
+
+#[test]
+fn growth_events_sparse() {
+    let mut set = BitSet::new();
+    set.set_growth(Growth::Exact);
+    let before = growth_events();
+    // every index lands in a new word of layer 1, and the first one also
+    // allocates layer 2.
+    for i in 0..10 {
+        set.add(i * 4_096);
+    }
+    assert_eq!(growth_events() - before, 3 + 9 * 2);
+
+    // indices within the allocation never grow the layers.
+    let before = growth_events();
+    for i in 0..36_864 {
+        set.add(i);
+    }
+    assert_eq!(growth_events(), before);
+}